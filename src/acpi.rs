@@ -0,0 +1,216 @@
+use crate::memory::phys_to_virt;
+use alloc::vec::Vec;
+use core::{mem, ptr, slice};
+use x86_64::PhysAddr;
+
+// RSDP 结构，版本 2 及以上在后面还有 XSDT 的地址
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // 以下字段只在版本 2 及以上存在
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+// 所有系统描述表共有的表头
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+// 读取一个物理地址上的（可能未对齐的）值
+unsafe fn read_phys<T: Copy>(addr: u64) -> T {
+    ptr::read_unaligned(phys_to_virt(PhysAddr::new(addr)).as_ptr::<T>())
+}
+
+// ACPI 表的所有字节之和必须为 0
+fn checksum_ok(addr: u64, len: usize) -> bool {
+    let bytes = unsafe { slice::from_raw_parts(phys_to_virt(PhysAddr::new(addr)).as_ptr::<u8>(), len) };
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+// 在 [start, end) 中按 16 字节对齐搜索 RSDP
+fn scan_rsdp(start: u64, end: u64) -> Option<u64> {
+    (start..end).step_by(16).find(|&addr| {
+        let signature: [u8; 8] = unsafe { read_phys(addr) };
+        if &signature != b"RSD PTR " || !checksum_ok(addr, 20) {
+            return false;
+        }
+        let revision: u8 = unsafe { read_phys(addr + 15) };
+        revision < 2 || checksum_ok(addr, mem::size_of::<Rsdp>())
+    })
+}
+
+/// 在 EBDA 的前 1 KiB 和 BIOS 只读区域 0xE0000..0x100000 中查找 RSDP
+pub fn find_rsdp() -> Option<PhysAddr> {
+    let ebda = u64::from(unsafe { read_phys::<u16>(0x40e) }) << 4;
+    let in_ebda = if ebda != 0 { scan_rsdp(ebda, ebda + 1024) } else { None };
+    in_ebda
+        .or_else(|| scan_rsdp(0xe0000, 0x100000))
+        .map(PhysAddr::new)
+}
+
+/// 通过 RSDT 或 XSDT 查找签名为 `signature` 的表
+pub fn find_table(signature: &[u8; 4]) -> Option<PhysAddr> {
+    let rsdp: Rsdp = unsafe { read_phys(find_rsdp()?.as_u64()) };
+    // 版本 2 及以上优先使用 64 位的 XSDT
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address, 8)
+    } else {
+        (u64::from(rsdp.rsdt_address), 4)
+    };
+    let header: SdtHeader = unsafe { read_phys(root) };
+    // 长度不足一个表头的根表已经损坏，不能用来计算表项数量
+    let entries_len = (header.length as usize).checked_sub(mem::size_of::<SdtHeader>())?;
+    if !checksum_ok(root, header.length as usize) {
+        return None;
+    }
+    let entries = entries_len / entry_size;
+    let first = root + mem::size_of::<SdtHeader>() as u64;
+
+    (0..entries as u64)
+        .map(|index| unsafe {
+            let entry = first + index * entry_size as u64;
+            if entry_size == 8 {
+                read_phys::<u64>(entry)
+            } else {
+                u64::from(read_phys::<u32>(entry))
+            }
+        })
+        .find(|&table| {
+            let header: SdtHeader = unsafe { read_phys(table) };
+            &header.signature == signature && checksum_ok(table, header.length as usize)
+        })
+        .map(PhysAddr::new)
+}
+
+/// MADT 中描述的一个处理器的 Local APIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicEntry {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+}
+
+/// MADT 中描述的一个 I/O APIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32, // 第一个输入引脚对应的全局系统中断号
+}
+
+/// 中断源覆盖：ISA IRQ `source` 实际连接到 `gsi`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16, // 位 0-1 为极性，位 2-3 为触发方式
+}
+
+impl InterruptSourceOverride {
+    /// 是否为低电平有效，未指定时使用 ISA 总线的默认值（高电平有效）
+    pub fn active_low(&self) -> bool {
+        self.flags & 0b11 == 0b11
+    }
+
+    /// 是否为电平触发，未指定时使用 ISA 总线的默认值（边沿触发）
+    pub fn level_triggered(&self) -> bool {
+        (self.flags >> 2) & 0b11 == 0b11
+    }
+}
+
+/// 解析后的 MADT（多 APIC 描述表）
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    /// 系统中同时存在兼容的双 8259 PIC
+    pub pc_at_compatible: bool,
+    pub local_apics: Vec<LocalApicEntry>,
+    pub io_apics: Vec<IoApicEntry>,
+    pub overrides: Vec<InterruptSourceOverride>,
+}
+
+/// 查找并解析 MADT
+pub fn madt() -> Option<Madt> {
+    let table = find_table(b"APIC")?.as_u64();
+    let header: SdtHeader = unsafe { read_phys(table) };
+    let body = table + mem::size_of::<SdtHeader>() as u64;
+    let end = table + u64::from(header.length);
+
+    let mut madt = Madt {
+        local_apic_address: u64::from(unsafe { read_phys::<u32>(body) }),
+        pc_at_compatible: unsafe { read_phys::<u32>(body + 4) } & 1 != 0,
+        local_apics: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    // 表头之后是 Local APIC 地址和标志，然后是变长的条目
+    let mut entry = body + 8;
+    while entry + 2 <= end {
+        let (kind, len): (u8, u8) = unsafe { (read_phys(entry), read_phys(entry + 1)) };
+        if len < 2 {
+            break; // 损坏的表
+        }
+        unsafe {
+            match kind {
+                0 => madt.local_apics.push(LocalApicEntry {
+                    processor_id: read_phys(entry + 2),
+                    apic_id: read_phys(entry + 3),
+                    enabled: read_phys::<u32>(entry + 4) & 1 != 0,
+                }),
+                1 => madt.io_apics.push(IoApicEntry {
+                    id: read_phys(entry + 2),
+                    address: read_phys(entry + 4),
+                    gsi_base: read_phys(entry + 8),
+                }),
+                2 => madt.overrides.push(InterruptSourceOverride {
+                    bus: read_phys(entry + 2),
+                    source: read_phys(entry + 3),
+                    gsi: read_phys(entry + 4),
+                    flags: read_phys(entry + 8),
+                }),
+                5 => madt.local_apic_address = read_phys(entry + 4), // 64 位地址覆盖
+                _ => {}
+            }
+        }
+        entry += u64::from(len);
+    }
+    Some(madt)
+}
+
+/// 查找 HPET 表，返回 HPET 寄存器块的物理地址
+pub fn hpet_address() -> Option<PhysAddr> {
+    let table = find_table(b"HPET")?.as_u64();
+    // 表头之后是事件定时器块 ID，然后是描述寄存器位置的通用地址结构
+    let base = table + mem::size_of::<SdtHeader>() as u64 + 4;
+    let (address_space, address): (u8, u64) = unsafe { (read_phys(base), read_phys(base + 4)) };
+    // 只支持映射在内存地址空间中的寄存器
+    (address_space == 0 && address != 0).then(|| PhysAddr::new(address))
+}
+
+#[test_case]
+fn test_madt_describes_an_io_apic() {
+    // QEMU 总是提供 ACPI 表和至少一个 I/O APIC
+    let madt = madt().expect("no MADT");
+    assert!(!madt.io_apics.is_empty());
+    assert!(madt.local_apics.iter().any(|apic| apic.enabled));
+}
@@ -0,0 +1,265 @@
+use crate::memory::{self, buddy::BuddyFrameAllocator};
+use alloc::alloc::{GlobalAlloc, Layout};
+use alloc::boxed::Box;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{OffsetPageTable, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+pub mod bump;
+#[cfg(feature = "heap-debug")]
+pub mod debug;
+pub mod fixed_size_block;
+pub mod linked_list;
+pub mod slab;
+
+// 内核堆所在的虚拟地址范围，起始地址按 2 MiB 对齐以便使用大页
+pub const HEAP_START: usize = 0x_4444_4440_0000;
+pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB，初始映射的大小
+/// 堆默认最多增长到的大小，可以用 `set_heap_limit` 调整
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+// 堆每次至少增长的大小
+const HEAP_GROW_STEP: usize = 64 * 1024;
+// 堆超过这个大小之后，每次增长到下一个 2 MiB 边界，新映射的部分可以使用大页
+const HEAP_HUGE_STEP: usize = 2 * 1024 * 1024;
+// 持有页表锁时堆无法增长，获取页表锁之前保证至少有这么多连续的空闲内存
+const HEAP_RESERVE: usize = 64 * 1024;
+
+// 已映射的堆的结束地址，只在持有分配器锁时修改
+static HEAP_END: AtomicUsize = AtomicUsize::new(HEAP_START + HEAP_SIZE);
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+
+// 通过 cargo feature 选择内核堆使用的分配器，必须且只能启用其中一个
+#[cfg(not(any(
+    feature = "bump-allocator",
+    feature = "linked-list-allocator",
+    feature = "fixed-size-block-allocator"
+)))]
+compile_error!("one of the `*-allocator` features must be enabled");
+
+#[cfg(any(
+    all(feature = "bump-allocator", feature = "linked-list-allocator"),
+    all(feature = "bump-allocator", feature = "fixed-size-block-allocator"),
+    all(feature = "linked-list-allocator", feature = "fixed-size-block-allocator")
+))]
+compile_error!("only one of the `*-allocator` features may be enabled");
+
+#[cfg(feature = "bump-allocator")]
+type KernelAllocator = bump::BumpAllocator;
+#[cfg(feature = "linked-list-allocator")]
+type KernelAllocator = linked_list::LinkedListAllocator;
+#[cfg(feature = "fixed-size-block-allocator")]
+type KernelAllocator = fixed_size_block::FixedSizeBlockAllocator;
+
+/// 当前启用的分配器的名称
+#[cfg(feature = "bump-allocator")]
+pub const DESIGN: &str = "bump";
+#[cfg(feature = "linked-list-allocator")]
+pub const DESIGN: &str = "linked-list";
+#[cfg(feature = "fixed-size-block-allocator")]
+pub const DESIGN: &str = "fixed-size-block";
+
+#[cfg(not(feature = "heap-debug"))]
+#[global_allocator]
+static ALLOCATOR: Locked<KernelAllocator> = Locked::new(KernelAllocator::new());
+
+// 启用 heap-debug 时用带红区、毒化和分配记录的包装替换全局分配器
+#[cfg(feature = "heap-debug")]
+#[global_allocator]
+static ALLOCATOR: debug::DebugAllocator<KernelAllocator> =
+    debug::DebugAllocator::new(KernelAllocator::new());
+
+const HEAP_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+/// 映射内核堆所在的页面，并用这段内存初始化全局分配器
+///
+/// 堆的起始地址按 2 MiB 对齐且足够大时会使用大页映射。
+pub fn init_heap(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BuddyFrameAllocator,
+) -> Result<(), MapToError<Size4KiB>> {
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    memory::map_range(mapper, heap_start, HEAP_SIZE as u64, HEAP_FLAGS, frame_allocator)?;
+
+    unsafe {
+        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+/// 当前已映射的堆大小
+pub fn heap_size() -> usize {
+    HEAP_END.load(Ordering::Relaxed) - HEAP_START
+}
+
+/// 设置堆最多增长到的大小，小于当前大小时不会收缩已映射的堆
+pub fn set_heap_limit(limit: usize) {
+    HEAP_LIMIT.store(limit, Ordering::Relaxed);
+}
+
+/// 堆最多增长到的大小
+pub fn heap_limit() -> usize {
+    HEAP_LIMIT.load(Ordering::Relaxed)
+}
+
+// 在堆的末尾映射至少 `min_size` 字节的新页面，返回新区域的起始地址和大小
+//
+// 调用者持有分配器的锁。页表可能正被打断的代码使用，所以只尝试获取一次，
+// 失败时本次分配返回空指针。
+fn grow(min_size: usize) -> Option<(usize, usize)> {
+    let start = HEAP_END.load(Ordering::Relaxed);
+    let end = if start - HEAP_START >= HEAP_HUGE_STEP {
+        align_up(start.checked_add(min_size)?, HEAP_HUGE_STEP)
+    } else {
+        start.checked_add(align_up(min_size.max(HEAP_GROW_STEP), 4096))?
+    };
+    // 对齐后超出上限时只增长到上限
+    let end = end.min(HEAP_START + heap_limit());
+    if end < start.checked_add(min_size)? {
+        return None;
+    }
+    let size = end - start;
+    let addr = VirtAddr::new(start as u64);
+    memory::try_with_mapper(|mapper, frame_allocator| {
+        memory::map_range(mapper, addr, size as u64, HEAP_FLAGS, frame_allocator)
+    })?
+    .ok()?;
+    HEAP_END.store(end, Ordering::Relaxed);
+    Some((start, size))
+}
+
+/// 保证堆中至少有 `HEAP_RESERVE` 字节连续的空闲内存，不够时扩展堆
+///
+/// 由 `memory::with_mapper` 在获取页表锁之前调用：持有页表锁时 `grow` 无法映射新页面，
+/// 闭包中的分配只能使用这部分预留的内存。
+pub fn reserve() {
+    let mut allocator = ALLOCATOR.lock();
+    if allocator.stats().largest_free_block >= HEAP_RESERVE {
+        return;
+    }
+    if let Some((start, size)) = grow(HEAP_RESERVE) {
+        unsafe { allocator.extend(start, size) };
+    }
+}
+
+/// 内核堆分配器的公共接口，由各个分配器实现
+pub trait HeapAllocator {
+    /// 分配一块满足 `layout` 的内存，内存不足时返回空指针
+    ///
+    /// # Safety
+    ///
+    /// 与 `GlobalAlloc::alloc` 相同。
+    unsafe fn allocate(&mut self, layout: Layout) -> *mut u8;
+
+    /// 释放由 `allocate` 分配的内存
+    ///
+    /// # Safety
+    ///
+    /// 与 `GlobalAlloc::dealloc` 相同。
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// 把紧接在堆末尾的 `[start, start + size)` 加入堆中
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证该内存范围已映射且未被使用。
+    unsafe fn extend(&mut self, start: usize, size: usize);
+}
+
+unsafe impl<A: HeapAllocator> GlobalAlloc for Locked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let ptr = allocator.allocate(layout);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        // 内存不足时扩展堆再重试，多出的部分用于对齐和分配器自身的元数据
+        match grow(layout.size() + layout.align()) {
+            Some((start, size)) => {
+                allocator.extend(start, size);
+                allocator.allocate(layout)
+            }
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().deallocate(ptr, layout)
+    }
+}
+
+/// 可失败的分配返回的错误，包含无法满足的布局
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError {
+    pub layout: Layout,
+}
+
+/// 分配一块满足 `layout` 的内存，失败时返回错误而不是进入分配错误处理函数
+///
+/// 返回的内存需要用 `alloc::alloc::dealloc` 和同一个布局释放。
+/// 零大小的布局不分配内存，返回一个按布局对齐的悬垂指针，它不能被释放。
+pub fn try_alloc(layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    if layout.size() == 0 {
+        // 零大小的分配不需要内存，全局分配器也不允许零大小的布局
+        return Ok(NonNull::new(layout.align() as *mut u8).unwrap());
+    }
+    NonNull::new(unsafe { alloc::alloc::alloc(layout) }).ok_or(AllocError { layout })
+}
+
+/// 与 `Box::new` 相同，但分配失败时返回错误
+pub fn try_box<T>(value: T) -> Result<Box<T>, AllocError> {
+    let ptr = try_alloc(Layout::new::<T>())?.cast::<T>().as_ptr();
+    unsafe {
+        ptr.write(value);
+        Ok(Box::from_raw(ptr))
+    }
+}
+
+/// 堆的使用统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub used: usize,               // 已分配（或无法再利用）的字节数
+    pub free: usize,               // 空闲字节数
+    pub largest_free_block: usize, // 最大的连续空闲块
+}
+
+impl HeapStats {
+    /// 外部碎片率（百分比）：空闲内存中不属于最大空闲块的比例
+    pub fn fragmentation_percent(&self) -> usize {
+        match (self.largest_free_block * 100).checked_div(self.free) {
+            Some(percent) => 100 - percent,
+            None => 0, // 没有空闲内存时不存在碎片
+        }
+    }
+}
+
+/// 返回内核堆当前的使用统计
+pub fn stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+/// 对 spin::Mutex 的包装，使我们可以为分配器实现 GlobalAlloc
+pub struct Locked<A> {
+    inner: spin::Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Locked {
+            inner: spin::Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> spin::MutexGuard<'_, A> {
+        self.inner.lock()
+    }
+}
+
+/// 将地址 `addr` 向上对齐到 `align`，`align` 必须是 2 的幂
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
@@ -0,0 +1,83 @@
+use super::{align_up, HeapAllocator, HeapStats};
+use alloc::alloc::Layout;
+use core::ptr;
+
+/// 只向前移动的“碰撞”分配器
+///
+/// 分配时只需把 `next` 指针向后移动；只有当所有分配都被释放后才能重用内存。
+pub struct BumpAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    next: usize,        // 下一次分配的起始地址
+    allocations: usize, // 尚未释放的分配数量
+}
+
+impl BumpAllocator {
+    /// 创建一个空的碰撞分配器
+    pub const fn new() -> Self {
+        BumpAllocator {
+            heap_start: 0,
+            heap_end: 0,
+            next: 0,
+            allocations: 0,
+        }
+    }
+
+    /// 使用给定的堆范围初始化分配器
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证该内存范围未被使用，并且此方法只调用一次。
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+    }
+
+    /// 返回当前的堆使用统计
+    pub fn stats(&self) -> HeapStats {
+        let free = self.heap_end - self.next;
+        HeapStats {
+            used: self.next - self.heap_start,
+            free,
+            largest_free_block: free,
+        }
+    }
+}
+
+impl Default for BumpAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapAllocator for BumpAllocator {
+    unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) => end,
+            None => return ptr::null_mut(),
+        };
+
+        if alloc_end > self.heap_end {
+            ptr::null_mut() // 内存不足
+        } else {
+            self.next = alloc_end;
+            self.allocations += 1;
+            alloc_start as *mut u8
+        }
+    }
+
+    unsafe fn deallocate(&mut self, _ptr: *mut u8, _layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start; // 所有分配都已释放，重置整个堆
+        }
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        // 新的内存紧接在堆的末尾，直接移动堆的结束地址即可
+        assert_eq!(start, self.heap_end, "heap must grow contiguously");
+        self.heap_end += size;
+    }
+}
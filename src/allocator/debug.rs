@@ -0,0 +1,305 @@
+use super::Locked;
+use crate::serial_println;
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{fmt, ptr};
+use spin::{Mutex, MutexGuard};
+
+// 每个分配前后的红区大小，前红区还会按分配的对齐方式向上取整
+const REDZONE: usize = 16;
+const REDZONE_BYTE: u8 = 0xfc;
+/// 新分配的内存被填充的字节，读到它通常意味着使用了未初始化的内存
+pub const ALLOC_POISON: u8 = 0xa5;
+/// 释放的内存被填充的字节，读到它通常意味着释放后使用
+pub const FREE_POISON: u8 = 0x6b;
+// 分配记录表最多记录的存活分配数量
+const MAX_RECORDS: usize = 2048;
+/// 每条分配记录保存的返回地址数量
+pub const BACKTRACE_DEPTH: usize = 6;
+
+/// 一个存活的分配
+#[derive(Debug, Clone, Copy)]
+pub struct Allocation {
+    pub ptr: usize,
+    pub size: usize,
+    /// 分配点的返回地址，最近的在前，未使用的为 0；最前面的几个通常位于 alloc 库内部
+    ///
+    /// 分配经过 `GlobalAlloc` 进入，无法使用 `#[track_caller]` 得到源码位置，
+    /// 这里只有地址，需要用 `addr2line` 等工具对照内核映像换算成文件和行号。
+    pub callers: [u64; BACKTRACE_DEPTH],
+    front: usize, // 前红区的长度
+}
+
+// 以 `return addresses [0x..., 0x...]` 的形式输出返回地址，表明它们不是源码位置
+struct Callers<'a>(&'a [u64; BACKTRACE_DEPTH]);
+
+impl fmt::Display for Callers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "return addresses [")?;
+        for (index, caller) in self.0.iter().take_while(|&&c| c != 0).enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:#x}", caller)?;
+        }
+        write!(f, "]")
+    }
+}
+
+// 沿帧指针链收集返回地址，依赖 `.cargo/config.toml` 中的 force-frame-pointers
+#[inline(always)]
+fn backtrace() -> [u64; BACKTRACE_DEPTH] {
+    let mut callers = [0; BACKTRACE_DEPTH];
+    let mut rbp: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+    for caller in callers.iter_mut() {
+        if rbp == 0 || !rbp.is_multiple_of(8) {
+            break;
+        }
+        let frame = rbp as *const u64;
+        let (next, ret) = unsafe { (*frame, *frame.add(1)) };
+        *caller = ret;
+        // 栈向下增长，上一帧必须位于更高的地址，否则帧指针链已经结束或损坏
+        if next <= rbp || next - rbp > 0x10_0000 {
+            break;
+        }
+        rbp = next;
+    }
+    callers
+}
+
+// 存活分配的记录表，存放在静态内存中，避免记录本身需要分配
+struct Table {
+    records: [Option<Allocation>; MAX_RECORDS],
+    live: usize,
+}
+
+impl Table {
+    const fn new() -> Self {
+        Table {
+            records: [None; MAX_RECORDS],
+            live: 0,
+        }
+    }
+
+    fn insert(&mut self, allocation: Allocation) -> bool {
+        match self.records.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(allocation);
+                self.live += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove(&mut self, ptr: usize) -> Option<Allocation> {
+        let slot = self
+            .records
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(allocation) if allocation.ptr == ptr))?;
+        self.live -= 1;
+        slot.take()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Allocation> {
+        self.records.iter().flatten()
+    }
+}
+
+/// 调试用的分配器包装
+///
+/// 每个分配前后各有一段红区，释放时检查红区是否被改写；新分配和已释放的内存
+/// 分别被填充为 `ALLOC_POISON` 和 `FREE_POISON`；存活的分配记录在一张表中，
+/// 释放不在表中的地址会被当作重复释放。发现错误时 panic 并给出分配点和释放点的返回地址。
+pub struct DebugAllocator<A> {
+    inner: Locked<A>,
+    table: Mutex<Table>,
+}
+
+impl<A> DebugAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        DebugAllocator {
+            inner: Locked::new(inner),
+            table: Mutex::new(Table::new()),
+        }
+    }
+
+    /// 锁住被包装的分配器
+    pub fn lock(&self) -> MutexGuard<'_, A> {
+        self.inner.lock()
+    }
+}
+
+// 返回包含红区的布局以及前红区的长度
+fn padded(layout: Layout) -> Option<(Layout, usize)> {
+    let front = super::align_up(REDZONE, layout.align());
+    let size = front.checked_add(layout.size())?.checked_add(REDZONE)?;
+    let padded = Layout::from_size_align(size, layout.align()).ok()?;
+    Some((padded, front))
+}
+
+// 检查分配的两段红区，返回第一个被改写字节相对于用户指针的偏移
+unsafe fn corrupted_redzone(allocation: &Allocation) -> Option<isize> {
+    let ptr = allocation.ptr as *const u8;
+    let front = (1..=allocation.front as isize).map(|offset| -offset);
+    let back = (0..REDZONE as isize).map(|offset| (allocation.size as isize) + offset);
+    front
+        .chain(back)
+        .find(|&offset| unsafe { *ptr.offset(offset) } != REDZONE_BYTE)
+}
+
+unsafe impl<A> GlobalAlloc for DebugAllocator<A>
+where
+    Locked<A>: GlobalAlloc,
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let callers = backtrace();
+        let (padded, front) = match padded(layout) {
+            Some(padded) => padded,
+            None => return ptr::null_mut(),
+        };
+        let base = self.inner.alloc(padded);
+        if base.is_null() {
+            return base;
+        }
+        let ptr = base.add(front);
+        ptr::write_bytes(base, REDZONE_BYTE, front);
+        ptr::write_bytes(ptr, ALLOC_POISON, layout.size());
+        ptr::write_bytes(ptr.add(layout.size()), REDZONE_BYTE, REDZONE);
+
+        let allocation = Allocation {
+            ptr: ptr as usize,
+            size: layout.size(),
+            callers,
+            front,
+        };
+        let inserted = self.table.lock().insert(allocation);
+        if !inserted {
+            panic!(
+                "heap-debug: allocation table is full ({} records), allocated from {}",
+                MAX_RECORDS,
+                Callers(&callers)
+            );
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let callers = backtrace();
+        // 先释放表的锁再 panic，这样 panic 之后仍然可以输出存活的分配
+        let removed = self.table.lock().remove(ptr as usize);
+        let allocation = match removed {
+            Some(allocation) => allocation,
+            None => panic!(
+                "heap-debug: double free or invalid free of {:p} ({} bytes), freed from {}",
+                ptr,
+                layout.size(),
+                Callers(&callers)
+            ),
+        };
+        if allocation.size != layout.size() {
+            panic!(
+                "heap-debug: {:p} was allocated with {} bytes but freed with {} bytes, freed from {}",
+                ptr,
+                allocation.size,
+                layout.size(),
+                Callers(&callers)
+            );
+        }
+        if let Some(offset) = corrupted_redzone(&allocation) {
+            panic!(
+                "heap-debug: redzone of {:p} ({} bytes) overwritten at offset {}, allocated from {}, freed from {}",
+                ptr,
+                allocation.size,
+                offset,
+                Callers(&allocation.callers),
+                Callers(&callers)
+            );
+        }
+
+        let (padded, front) = padded(layout).expect("layout was accepted by alloc");
+        let base = ptr.sub(front);
+        ptr::write_bytes(base, FREE_POISON, padded.size());
+        self.inner.dealloc(base, padded);
+    }
+}
+
+/// 当前存活的分配数量
+pub fn live_allocations() -> usize {
+    super::ALLOCATOR.table.lock().live
+}
+
+/// 对每个存活的分配调用 `f`
+///
+/// 调用期间持有记录表的锁，`f` 中不能进行堆分配。
+pub fn for_each_live(mut f: impl FnMut(&Allocation)) {
+    for allocation in super::ALLOCATOR.table.lock().iter() {
+        f(allocation);
+    }
+}
+
+/// 检查所有存活分配的红区，发现被改写的红区时 panic
+pub fn check_redzones() {
+    let corrupted = super::ALLOCATOR.table.lock().iter().find_map(|allocation| {
+        unsafe { corrupted_redzone(allocation) }.map(|offset| (*allocation, offset))
+    });
+    if let Some((allocation, offset)) = corrupted {
+        panic!(
+            "heap-debug: redzone of {:#x} ({} bytes) overwritten at offset {}, allocated from {}",
+            allocation.ptr,
+            allocation.size,
+            offset,
+            Callers(&allocation.callers)
+        );
+    }
+}
+
+/// 通过串口输出所有存活的分配
+///
+/// 每行依次为用户地址、大小和分配点的返回地址（不是源码位置）。
+pub fn dump_live() {
+    serial_println!("live allocations begin ({})", live_allocations());
+    for_each_live(|allocation| {
+        serial_println!(
+            "{:#018x} {:>8} {}",
+            allocation.ptr,
+            allocation.size,
+            Callers(&allocation.callers)
+        );
+    });
+    serial_println!("live allocations end");
+}
+
+#[test_case]
+fn test_allocations_are_tracked() {
+    use alloc::boxed::Box;
+
+    let before = live_allocations();
+    let value = Box::new([0u8; 40]);
+    assert_eq!(live_allocations(), before + 1);
+    let address = &*value as *const _ as usize;
+    let mut found = false;
+    for_each_live(|allocation| {
+        if allocation.ptr == address {
+            found = allocation.size == 40 && allocation.callers[0] != 0;
+        }
+    });
+    assert!(found);
+    drop(value);
+    assert_eq!(live_allocations(), before);
+}
+
+#[test_case]
+fn test_memory_is_poisoned() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let ptr = alloc::alloc::alloc(layout);
+        assert!((0..64).all(|i| *ptr.add(i) == ALLOC_POISON));
+        alloc::alloc::dealloc(ptr, layout);
+        // 释放后的内存中只有分配器自己的元数据可能覆盖毒化字节
+        let poisoned = (0..64).filter(|&i| ptr.add(i).read_volatile() == FREE_POISON);
+        assert!(poisoned.count() >= 32);
+    }
+}
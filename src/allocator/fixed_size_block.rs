@@ -0,0 +1,128 @@
+use super::linked_list::LinkedListAllocator;
+use super::{HeapAllocator, HeapStats};
+use alloc::alloc::Layout;
+use core::mem;
+
+/// 可用的块大小
+///
+/// 块大小必须是 2 的幂，因为它们同时被用作块的对齐方式。
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+// 空闲块链表中的节点
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+/// 固定大小块分配器
+///
+/// 小于等于 2048 字节的分配从对应大小的空闲块链表中取出，
+/// 更大的分配交给后备的链表分配器处理。
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback_allocator: LinkedListAllocator,
+}
+
+impl FixedSizeBlockAllocator {
+    /// 创建一个空的固定大小块分配器
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+        }
+    }
+
+    /// 使用给定的堆范围初始化分配器
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证该内存范围未被使用，并且此方法只调用一次。
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback_allocator.init(heap_start, heap_size);
+    }
+
+    // 使用后备分配器进行分配
+    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        unsafe { self.fallback_allocator.allocate(layout) }
+    }
+
+    /// 返回当前的堆使用统计
+    ///
+    /// 空闲链表中的块也计入空闲内存，但它们只能满足同样大小的分配。
+    pub fn stats(&self) -> HeapStats {
+        let fallback = self.fallback_allocator.stats();
+        let mut free = fallback.free;
+        let mut largest_free_block = fallback.largest_free_block;
+        for (index, head) in self.list_heads.iter().enumerate() {
+            let mut current = head.as_deref();
+            while let Some(node) = current {
+                free += BLOCK_SIZES[index];
+                largest_free_block = largest_free_block.max(BLOCK_SIZES[index]);
+                current = node.next.as_deref();
+            }
+        }
+        HeapStats {
+            used: fallback.used + fallback.free - free,
+            free,
+            largest_free_block,
+        }
+    }
+}
+
+impl Default for FixedSizeBlockAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 为给定布局选择合适的块大小，返回其在 BLOCK_SIZES 中的下标
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
+
+impl HeapAllocator for FixedSizeBlockAllocator {
+    unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        match list_index(&layout) {
+            Some(index) => {
+                match self.list_heads[index].take() {
+                    Some(node) => {
+                        self.list_heads[index] = node.next.take();
+                        node as *mut ListNode as *mut u8
+                    }
+                    None => {
+                        // 链表中没有空闲块，从后备分配器分配一个新块
+                        let block_size = BLOCK_SIZES[index];
+                        // 块大小是 2 的幂，所以可以直接用作对齐
+                        let block_align = block_size;
+                        let layout = Layout::from_size_align(block_size, block_align).unwrap();
+                        self.fallback_alloc(layout)
+                    }
+                }
+            }
+            None => self.fallback_alloc(layout),
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        match list_index(&layout) {
+            Some(index) => {
+                let new_node = ListNode {
+                    next: self.list_heads[index].take(),
+                };
+                // 确保块的大小和对齐足以存放 ListNode
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(new_node);
+                self.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => self.fallback_allocator.deallocate(ptr, layout),
+        }
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        // 新的内存交给后备分配器，空闲链表中的块按需从中切出
+        self.fallback_allocator.extend(start, size);
+    }
+}
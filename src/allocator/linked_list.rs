@@ -0,0 +1,200 @@
+use super::{align_up, HeapAllocator, HeapStats};
+use alloc::alloc::Layout;
+use core::{mem, ptr};
+
+// 空闲链表中的节点，直接存放在空闲内存块的起始处
+struct ListNode {
+    size: usize,
+    next: Option<&'static mut ListNode>,
+}
+
+impl ListNode {
+    const fn new(size: usize) -> Self {
+        ListNode { size, next: None }
+    }
+
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+/// 基于空闲链表的分配器
+///
+/// 空闲块按地址排序，释放时会与相邻的空闲块合并，以减少碎片。
+pub struct LinkedListAllocator {
+    head: ListNode, // 哨兵节点，本身不代表任何内存
+    heap_size: usize,
+}
+
+impl LinkedListAllocator {
+    /// 创建一个空的链表分配器
+    pub const fn new() -> Self {
+        Self {
+            head: ListNode::new(0),
+            heap_size: 0,
+        }
+    }
+
+    /// 使用给定的堆范围初始化分配器
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证该内存范围未被使用，并且此方法只调用一次。
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_size = heap_size;
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    // 按地址顺序把内存区域加入空闲链表，并与前后相邻的空闲块合并
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        // 确保空闲区域能容纳一个 ListNode
+        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        // 找到插入位置：prev 为最后一个起始地址小于 addr 的节点
+        let head: *mut ListNode = &mut self.head;
+        let mut prev = head;
+        while let Some(next) = (*prev).next.as_deref_mut() {
+            if next.start_addr() > addr {
+                break;
+            }
+            prev = next;
+        }
+
+        // 在 prev 之后插入新节点
+        let node_ptr = addr as *mut ListNode;
+        let mut node = ListNode::new(size);
+        node.next = (*prev).next.take();
+        node_ptr.write(node);
+        let node = &mut *node_ptr;
+
+        // 与后一个空闲块相邻时合并
+        if let Some(next) = node.next.take() {
+            if node.end_addr() == next.start_addr() {
+                node.size += next.size;
+                node.next = next.next.take();
+            } else {
+                node.next = Some(next);
+            }
+        }
+
+        // 与前一个空闲块相邻时合并（哨兵节点除外）
+        if prev != head && (*prev).end_addr() == addr {
+            (*prev).size += node.size;
+            (*prev).next = node.next.take();
+        } else {
+            (*prev).next = Some(node);
+        }
+    }
+
+    // 查找满足布局要求的空闲块，并将其从链表中移除
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let mut current = &mut self.head;
+        while let Some(ref mut region) = current.next {
+            if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
+                // 找到合适的区域，把它从链表中移除
+                let next = region.next.take();
+                let ret = Some((current.next.take().unwrap(), alloc_start));
+                current.next = next;
+                return ret;
+            } else {
+                current = current.next.as_mut().unwrap();
+            }
+        }
+        None
+    }
+
+    // 尝试在给定区域中进行分配，成功时返回分配的起始地址
+    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
+        let mut alloc_start = align_up(region.start_addr(), align);
+        if alloc_start != region.start_addr()
+            && alloc_start - region.start_addr() < mem::size_of::<ListNode>()
+        {
+            // 对齐产生的前部空隙放不下一个 ListNode，向后挪到下一个对齐位置
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
+        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
+
+        if alloc_end > region.end_addr() {
+            return Err(()); // 区域太小
+        }
+
+        let excess_size = region.end_addr() - alloc_end;
+        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
+            // 剩余部分放不下一个 ListNode
+            return Err(());
+        }
+
+        Ok(alloc_start)
+    }
+
+    // 调整布局，使分配出的区域也能存放一个 ListNode
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        let size = layout.size().max(mem::size_of::<ListNode>());
+        (size, layout.align())
+    }
+
+    /// 返回当前的堆使用统计
+    pub fn stats(&self) -> HeapStats {
+        let mut free = 0;
+        let mut largest_free_block = 0;
+        let mut current = &self.head;
+        while let Some(region) = current.next.as_deref() {
+            free += region.size;
+            largest_free_block = largest_free_block.max(region.size);
+            current = region;
+        }
+        HeapStats {
+            used: self.heap_size - free,
+            free,
+            largest_free_block,
+        }
+    }
+}
+
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeapAllocator for LinkedListAllocator {
+    unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        if let Some((region, alloc_start)) = self.find_region(size, align) {
+            let alloc_end = alloc_start.checked_add(size).expect("overflow");
+            let start_addr = region.start_addr();
+            let end_addr = region.end_addr();
+            // 对齐产生的前部空隙和剩余的尾部重新加入空闲链表
+            if alloc_start > start_addr {
+                self.add_free_region(start_addr, alloc_start - start_addr);
+            }
+            if end_addr > alloc_end {
+                self.add_free_region(alloc_end, end_addr - alloc_end);
+            }
+            alloc_start as *mut u8
+        } else {
+            ptr::null_mut()
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        self.add_free_region(ptr as usize, size)
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        // 新区域与末尾的空闲块相邻时会被合并
+        self.heap_size += size;
+        self.add_free_region(start, size);
+    }
+}
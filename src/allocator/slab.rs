@@ -0,0 +1,306 @@
+use crate::memory;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use core::{fmt, mem};
+use spin::Mutex;
+use x86_64::structures::paging::PhysFrame;
+
+// 每个 slab 至少容纳的对象数量，对象太大时会使用更高阶的块
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+// slab 最多占用 2^MAX_SLAB_ORDER 个帧
+const MAX_SLAB_ORDER: usize = 3;
+
+// slab 头部，存放在 slab 所占内存的起始处
+struct SlabHeader {
+    next: *mut SlabHeader,
+    free: *mut FreeObject, // 该 slab 中空闲对象组成的链表
+    in_use: usize,
+    frame: PhysFrame,
+}
+
+// 空闲对象槽中存放的链表节点
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// 一个对象缓存的使用统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub object_size: usize,      // 每个对象槽的字节数
+    pub objects_per_slab: usize, // 每个 slab 的对象槽数量
+    pub slabs: usize,            // 当前持有的 slab 数量
+    pub active_objects: usize,   // 正在使用的对象数量
+}
+
+/// 固定大小内核对象的缓存（slab 分配器）
+///
+/// 每个 slab 是从伙伴分配器得到的一段连续物理帧，通过物理内存映射访问，
+/// 因此与全局堆互不影响。
+///
+/// ```ignore
+/// static TASKS: ObjectCache<Task> = ObjectCache::new("task");
+/// let task = TASKS.alloc(Task::new()).expect("out of memory");
+/// ```
+pub struct ObjectCache<T> {
+    name: &'static str,
+    constructor: Option<fn() -> T>,
+    slabs: Mutex<*mut SlabHeader>, // slab 链表的头节点
+    _marker: PhantomData<T>,
+}
+
+// slab 链表只在持有锁时访问，对象本身只会通过 SlabBox 交给一个使用者
+unsafe impl<T: Send> Send for ObjectCache<T> {}
+unsafe impl<T: Send> Sync for ObjectCache<T> {}
+
+impl<T> ObjectCache<T> {
+    /// 创建一个名为 `name` 的空缓存，首次分配时才会申请 slab
+    pub const fn new(name: &'static str) -> Self {
+        ObjectCache {
+            name,
+            constructor: None,
+            slabs: Mutex::new(ptr::null_mut()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// 创建一个带构造函数的缓存，`construct` 会用它初始化新对象
+    pub const fn with_constructor(name: &'static str, constructor: fn() -> T) -> Self {
+        ObjectCache {
+            name,
+            constructor: Some(constructor),
+            slabs: Mutex::new(ptr::null_mut()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// 缓存的名称
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // 每个对象槽的大小和对齐，槽中必须能放下一个 FreeObject
+    fn slot_layout() -> (usize, usize) {
+        let align = mem::align_of::<T>().max(mem::align_of::<FreeObject>());
+        let size = mem::size_of::<T>().max(mem::size_of::<FreeObject>());
+        ((size + align - 1) & !(align - 1), align)
+    }
+
+    // 第一个对象槽相对 slab 起始处的偏移
+    fn first_slot_offset() -> usize {
+        let (_, align) = Self::slot_layout();
+        (mem::size_of::<SlabHeader>() + align - 1) & !(align - 1)
+    }
+
+    // slab 使用的阶，尽量让每个 slab 至少容纳 MIN_OBJECTS_PER_SLAB 个对象
+    fn slab_order() -> usize {
+        let (size, _) = Self::slot_layout();
+        let needed = Self::first_slot_offset() + size * MIN_OBJECTS_PER_SLAB;
+        (0..=MAX_SLAB_ORDER)
+            .find(|&order| 4096 << order >= needed)
+            .unwrap_or(MAX_SLAB_ORDER)
+    }
+
+    fn slab_bytes() -> usize {
+        4096 << Self::slab_order()
+    }
+
+    fn objects_per_slab() -> usize {
+        let (size, _) = Self::slot_layout();
+        (Self::slab_bytes() - Self::first_slot_offset()) / size
+    }
+
+    // 从帧分配器申请一个新的 slab 并初始化其空闲对象链表
+    unsafe fn grow(&self) -> Option<*mut SlabHeader> {
+        if Self::objects_per_slab() == 0 {
+            return None; // 对象太大，无法放入 slab
+        }
+        let frame = memory::FRAME_ALLOCATOR
+            .lock()
+            .as_mut()?
+            .allocate_contiguous(Self::slab_order())?;
+        let start = memory::phys_to_virt(frame.start_address()).as_u64() as usize;
+
+        let (size, _) = Self::slot_layout();
+        let mut free = ptr::null_mut();
+        // 逆序建立链表，使分配按地址从低到高进行
+        for index in (0..Self::objects_per_slab()).rev() {
+            let slot = (start + Self::first_slot_offset() + index * size) as *mut FreeObject;
+            slot.write(FreeObject { next: free });
+            free = slot;
+        }
+
+        let header = start as *mut SlabHeader;
+        header.write(SlabHeader {
+            next: ptr::null_mut(),
+            free,
+            in_use: 0,
+            frame,
+        });
+        Some(header)
+    }
+
+    // 取出一个空闲对象槽，必要时申请新的 slab
+    fn alloc_slot(&self) -> Option<NonNull<T>> {
+        let mut head = self.slabs.lock();
+        unsafe {
+            let mut slab = *head;
+            while !slab.is_null() && (*slab).free.is_null() {
+                slab = (*slab).next;
+            }
+            if slab.is_null() {
+                slab = self.grow()?;
+                (*slab).next = *head;
+                *head = slab;
+            }
+            let object = (*slab).free;
+            (*slab).free = (*object).next;
+            (*slab).in_use += 1;
+            NonNull::new(object as *mut T)
+        }
+    }
+
+    // 把对象槽放回其所属 slab 的空闲链表
+    unsafe fn free_slot(&self, object: NonNull<T>) {
+        let head = self.slabs.lock();
+        let addr = object.as_ptr() as usize;
+        let mut slab = *head;
+        while !slab.is_null() {
+            let start = slab as usize;
+            if (start..start + Self::slab_bytes()).contains(&addr) {
+                let object = addr as *mut FreeObject;
+                object.write(FreeObject { next: (*slab).free });
+                (*slab).free = object;
+                (*slab).in_use -= 1;
+                return;
+            }
+            slab = (*slab).next;
+        }
+        panic!("object {:#x} does not belong to cache {}", addr, self.name);
+    }
+
+    /// 分配一个对象并用 `value` 初始化，内存不足时返回 `None`
+    pub fn alloc(&self, value: T) -> Option<SlabBox<'_, T>> {
+        let object = self.alloc_slot()?;
+        unsafe { object.as_ptr().write(value) };
+        Some(SlabBox {
+            cache: self,
+            object,
+        })
+    }
+
+    /// 分配一个对象并用缓存的构造函数初始化
+    pub fn construct(&self) -> Option<SlabBox<'_, T>> {
+        let constructor = self
+            .constructor
+            .unwrap_or_else(|| panic!("cache {} has no constructor", self.name));
+        self.alloc(constructor())
+    }
+
+    /// 把完全空闲的 slab 还给帧分配器，返回释放的 slab 数量
+    pub fn shrink(&self) -> usize {
+        let mut head = self.slabs.lock();
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().expect("memory not initialized");
+        let mut released = 0;
+        unsafe {
+            let mut link: *mut *mut SlabHeader = &mut *head;
+            while !(*link).is_null() {
+                let slab = *link;
+                if (*slab).in_use == 0 {
+                    *link = (*slab).next;
+                    frame_allocator.deallocate((*slab).frame, Self::slab_order());
+                    released += 1;
+                } else {
+                    link = &mut (*slab).next;
+                }
+            }
+        }
+        released
+    }
+
+    /// 返回缓存的使用统计
+    pub fn stats(&self) -> CacheStats {
+        let head = self.slabs.lock();
+        let mut slabs = 0;
+        let mut active_objects = 0;
+        let mut slab = *head;
+        while !slab.is_null() {
+            unsafe {
+                slabs += 1;
+                active_objects += (*slab).in_use;
+                slab = (*slab).next;
+            }
+        }
+        CacheStats {
+            name: self.name,
+            object_size: Self::slot_layout().0,
+            objects_per_slab: Self::objects_per_slab(),
+            slabs,
+            active_objects,
+        }
+    }
+}
+
+/// 指向对象缓存中一个对象的智能指针，离开作用域时把对象还给缓存
+pub struct SlabBox<'a, T> {
+    cache: &'a ObjectCache<T>,
+    object: NonNull<T>,
+}
+
+impl<T> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.object.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.object.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.object.as_ptr());
+            self.cache.free_slot(self.object);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SlabBox<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[test_case]
+fn test_object_cache_reuses_and_shrinks() {
+    use alloc::vec::Vec;
+
+    static CACHE: ObjectCache<[u64; 16]> = ObjectCache::with_constructor("test", || [7; 16]);
+
+    let frames_before = memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().free_frames();
+    let per_slab = CACHE.stats().objects_per_slab;
+
+    // 分配超过一个 slab 的对象，使缓存申请第二个 slab
+    let objects: Vec<_> = (0..per_slab + 1)
+        .map(|_| CACHE.construct().expect("out of memory"))
+        .collect();
+    assert!(objects.iter().all(|object| object[15] == 7));
+    let stats = CACHE.stats();
+    assert_eq!(stats.slabs, 2);
+    assert_eq!(stats.active_objects, per_slab + 1);
+
+    drop(objects);
+    assert_eq!(CACHE.stats().active_objects, 0);
+    assert_eq!(CACHE.shrink(), 2);
+    assert_eq!(CACHE.stats().slabs, 0);
+
+    let frames_after = memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().free_frames();
+    assert_eq!(frames_before, frames_after);
+}
@@ -0,0 +1,338 @@
+use crate::memory::buddy::BuddyFrameAllocator;
+use crate::memory::{self, protect};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::{OffsetPageTable, Page, PageTableFlags, Translate};
+use x86_64::VirtAddr;
+
+/// 同时存在的断点数量上限
+pub const MAX_BREAKPOINTS: usize = 16;
+
+const INT3: u8 = 0xcc;
+
+/// 命中断点时调用的函数，在关中断的异常上下文中运行
+///
+/// 栈帧中的指令指针指向断点所在的指令。
+pub type BreakpointCallback = fn(addr: VirtAddr, stack_frame: &InterruptStackFrame);
+
+/// 设置或移除断点时可能出现的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakpointError {
+    /// 地址不在内核的 `.text` 段中
+    NotInText(VirtAddr),
+    /// 该地址上已经有断点
+    AlreadySet(VirtAddr),
+    /// 该地址上没有断点
+    NotSet(VirtAddr),
+    /// 断点数量已达上限
+    TooManyBreakpoints,
+    /// 无法临时修改代码页的权限
+    PatchFailed(VirtAddr),
+    /// 该地址上的指令读取或修改 IF，无法在关中断时单步执行
+    UnsupportedInstruction(VirtAddr),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    addr: VirtAddr,
+    original: u8,
+    hits: u64,
+    // 其他断点单步期间被命中而临时恢复了原来的字节，单步完成后重新写入 int3
+    rearm: bool,
+}
+
+// 正在单步执行的断点，以及命中前中断是否打开
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    addr: VirtAddr,
+    interrupts_enabled: bool,
+}
+
+// 只在关中断时访问
+static BREAKPOINTS: Mutex<[Option<Breakpoint>; MAX_BREAKPOINTS]> = Mutex::new([None; MAX_BREAKPOINTS]);
+static CALLBACK: Mutex<Option<BreakpointCallback>> = Mutex::new(None);
+static STEPPING: Mutex<Option<Step>> = Mutex::new(None);
+
+/// 设置命中断点时调用的函数
+pub fn set_callback(callback: Option<BreakpointCallback>) {
+    without_interrupts(|| *CALLBACK.lock() = callback);
+}
+
+// 把 addr 处的一个字节改为 byte 并返回原来的值，代码页在写入期间临时设为可写
+fn patch_with_mapper(
+    mapper: &mut OffsetPageTable<'static>,
+    frame_allocator: &mut BuddyFrameAllocator,
+    addr: VirtAddr,
+    byte: u8,
+) -> Result<u8, BreakpointError> {
+    let TranslateResult::Mapped { flags, .. } = mapper.translate(addr) else {
+        return Err(BreakpointError::PatchFailed(addr));
+    };
+    // 大页会被拆分成 4 KiB 页面，之后修改权限不再需要分配页表
+    let flags = flags & !PageTableFlags::HUGE_PAGE;
+    let page = Page::containing_address(addr);
+    unsafe {
+        memory::split_and_update_flags(mapper, page, flags | PageTableFlags::WRITABLE, frame_allocator)
+            .map_err(|_| BreakpointError::PatchFailed(addr))?;
+        let ptr = addr.as_mut_ptr::<u8>();
+        let original = ptr.read_volatile();
+        ptr.write_volatile(byte);
+        memory::update_flags(mapper, page, flags).map_err(|_| BreakpointError::PatchFailed(addr))?;
+        Ok(original)
+    }
+}
+
+// 在异常处理函数中修改代码。被打断的代码持有页表的锁时，临时关闭 CR0.WP 直接写入，
+// 此时已经关中断，不会有其他代码在 CR0.WP 关闭期间运行。
+fn patch_in_exception(addr: VirtAddr, byte: u8) {
+    let patched = memory::try_with_mapper(|mapper, frame_allocator| {
+        patch_with_mapper(mapper, frame_allocator, addr, byte)
+    });
+    if !matches!(patched, Some(Ok(_))) {
+        unsafe {
+            Cr0::update(|flags| flags.remove(Cr0Flags::WRITE_PROTECT));
+            addr.as_mut_ptr::<u8>().write_volatile(byte);
+            Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+        }
+    }
+}
+
+// 单步执行时 IF 被临时清除，读取或修改 IF 的指令（cli、sti、pushf、popf、iret）
+// 会看到或改掉这个临时的值
+fn uses_interrupt_flag(addr: VirtAddr) -> bool {
+    let code = addr.as_ptr::<u8>();
+    let mut offset = 0;
+    loop {
+        let byte = unsafe { code.add(offset).read_volatile() };
+        match byte {
+            // 跳过操作数大小前缀和 REX 前缀
+            0x66 | 0x40..=0x4f if offset < 2 => offset += 1,
+            _ => return matches!(byte, 0xfa | 0xfb | 0x9c | 0x9d | 0xcf),
+        }
+    }
+}
+
+/// 在 `addr` 处设置断点：保存原来的字节并写入 `int3`
+///
+/// `addr` 必须是一条指令的起始地址。不能在断点和页表管理的代码中设置断点，
+/// 也不能在读取或修改 IF 的指令上设置断点。
+pub fn set_breakpoint(addr: VirtAddr) -> Result<(), BreakpointError> {
+    let text = protect::sections()[0];
+    if addr < text.start || addr >= text.end {
+        return Err(BreakpointError::NotInText(addr));
+    }
+    without_interrupts(|| {
+        let mut breakpoints = BREAKPOINTS.lock();
+        if breakpoints.iter().flatten().any(|bp| bp.addr == addr) {
+            return Err(BreakpointError::AlreadySet(addr));
+        }
+        if uses_interrupt_flag(addr) {
+            return Err(BreakpointError::UnsupportedInstruction(addr));
+        }
+        let slot = breakpoints
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(BreakpointError::TooManyBreakpoints)?;
+        let original = memory::with_mapper(|mapper, frame_allocator| {
+            patch_with_mapper(mapper, frame_allocator, addr, INT3)
+        })?;
+        *slot = Some(Breakpoint {
+            addr,
+            original,
+            hits: 0,
+            rearm: false,
+        });
+        Ok(())
+    })
+}
+
+/// 移除 `addr` 处的断点，恢复原来的字节
+pub fn remove_breakpoint(addr: VirtAddr) -> Result<(), BreakpointError> {
+    without_interrupts(|| {
+        let mut breakpoints = BREAKPOINTS.lock();
+        let slot = breakpoints
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|bp| bp.addr == addr))
+            .ok_or(BreakpointError::NotSet(addr))?;
+        let original = slot.expect("slot was checked").original;
+        memory::with_mapper(|mapper, frame_allocator| {
+            patch_with_mapper(mapper, frame_allocator, addr, original)
+        })?;
+        *slot = None;
+        Ok(())
+    })
+}
+
+/// `addr` 处断点的命中次数，没有断点时返回 None
+pub fn hit_count(addr: VirtAddr) -> Option<u64> {
+    without_interrupts(|| {
+        BREAKPOINTS
+            .lock()
+            .iter()
+            .flatten()
+            .find(|bp| bp.addr == addr)
+            .map(|bp| bp.hits)
+    })
+}
+
+/// 由 #BP 处理函数调用，返回 false 表示这不是通过 `set_breakpoint` 设置的断点
+///
+/// 命中时调用回调函数，恢复原来的指令，然后设置 TF 单步执行它。
+/// 另一个断点正在单步执行时（例如在单步引起的异常处理函数中命中），这次命中不计数，
+/// 只临时恢复原来的指令，等那次单步完成后再重新写入 `int3`。
+pub(crate) fn handle_breakpoint(stack_frame: &mut InterruptStackFrame) -> bool {
+    // int3 是陷阱，返回地址在断点的下一个字节
+    let addr = stack_frame.instruction_pointer - 1u64;
+    let Some(mut breakpoints) = BREAKPOINTS.try_lock() else {
+        return false;
+    };
+    let Some(bp) = breakpoints.iter_mut().flatten().find(|bp| bp.addr == addr) else {
+        return false;
+    };
+    let original = bp.original;
+    let nested = STEPPING.lock().is_some();
+    if nested {
+        bp.rearm = true;
+    } else {
+        bp.hits += 1;
+    }
+    drop(breakpoints);
+
+    unsafe {
+        stack_frame
+            .as_mut()
+            .update(|frame| frame.instruction_pointer = addr);
+    }
+    if nested {
+        patch_in_exception(addr, original);
+        return true;
+    }
+    let callback = *CALLBACK.lock();
+    if let Some(callback) = callback {
+        callback(addr, stack_frame);
+    }
+
+    patch_in_exception(addr, original);
+    let flags = stack_frame.cpu_flags;
+    *STEPPING.lock() = Some(Step {
+        addr,
+        interrupts_enabled: flags & RFlags::INTERRUPT_FLAG.bits() != 0,
+    });
+    // 单步期间关中断，避免中断处理函数在断点被恢复时经过这里
+    unsafe {
+        stack_frame.as_mut().update(|frame| {
+            frame.cpu_flags = (flags | RFlags::TRAP_FLAG.bits()) & !RFlags::INTERRUPT_FLAG.bits();
+        });
+    }
+    true
+}
+
+/// 由 #DB 处理函数调用，返回 false 表示这不是断点引起的单步
+///
+/// 单步完成后重新写入 `int3`（包括单步期间被临时恢复的断点），并恢复 TF 和 IF。
+/// 断点不会设置在读取或修改 IF 的指令上，所以单步前保存的 IF 仍然有效。
+pub(crate) fn handle_single_step(stack_frame: &mut InterruptStackFrame) -> bool {
+    let Some(step) = STEPPING.lock().take() else {
+        return false;
+    };
+    // 回调函数中可能已经移除了断点，此时它不在列表中
+    if let Some(mut breakpoints) = BREAKPOINTS.try_lock() {
+        for bp in breakpoints.iter_mut().flatten() {
+            if bp.addr == step.addr || bp.rearm {
+                bp.rearm = false;
+                patch_in_exception(bp.addr, INT3);
+            }
+        }
+    }
+    unsafe {
+        stack_frame.as_mut().update(|frame| {
+            frame.cpu_flags &= !RFlags::TRAP_FLAG.bits();
+            if step.interrupts_enabled {
+                frame.cpu_flags |= RFlags::INTERRUPT_FLAG.bits();
+            }
+        });
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    static CALLBACK_HITS: AtomicU64 = AtomicU64::new(0);
+
+    #[inline(never)]
+    fn target(x: u64) -> u64 {
+        x * 3 + 1
+    }
+
+    fn count_hit(addr: VirtAddr, stack_frame: &InterruptStackFrame) {
+        assert_eq!(stack_frame.instruction_pointer, addr);
+        CALLBACK_HITS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test_case]
+    fn test_breakpoint_is_resumable_and_rearmed() {
+        let function = core::hint::black_box(target as fn(u64) -> u64);
+        let addr = VirtAddr::from_ptr(function as *const ());
+        let original = unsafe { addr.as_ptr::<u8>().read_volatile() };
+        CALLBACK_HITS.store(0, Ordering::Relaxed);
+        set_callback(Some(count_hit));
+
+        set_breakpoint(addr).unwrap();
+        assert_eq!(unsafe { addr.as_ptr::<u8>().read_volatile() }, INT3);
+        // 每次调用都命中断点，并且函数的行为不变
+        assert_eq!(function(5), 16);
+        assert_eq!(function(7), 22);
+        assert_eq!(hit_count(addr), Some(2));
+        assert_eq!(CALLBACK_HITS.load(Ordering::Relaxed), 2);
+
+        remove_breakpoint(addr).unwrap();
+        assert_eq!(function(1), 4);
+        assert_eq!(CALLBACK_HITS.load(Ordering::Relaxed), 2);
+        assert_eq!(unsafe { addr.as_ptr::<u8>().read_volatile() }, original);
+        set_callback(None);
+    }
+
+    #[test_case]
+    fn test_breakpoint_errors() {
+        let data = 0u8;
+        let addr = VirtAddr::from_ptr(&data);
+        assert_eq!(set_breakpoint(addr), Err(BreakpointError::NotInText(addr)));
+
+        let code = VirtAddr::from_ptr(target as *const ());
+        assert_eq!(remove_breakpoint(code), Err(BreakpointError::NotSet(code)));
+        set_breakpoint(code).unwrap();
+        assert_eq!(set_breakpoint(code), Err(BreakpointError::AlreadySet(code)));
+        remove_breakpoint(code).unwrap();
+    }
+
+    // 只用来设置断点，从不调用
+    core::arch::global_asm!(
+        ".pushsection .text",
+        ".global breakpoint_test_popfq",
+        "breakpoint_test_popfq:",
+        "popfq",
+        "ret",
+        ".popsection",
+    );
+
+    extern "C" {
+        fn breakpoint_test_popfq();
+    }
+
+    #[test_case]
+    fn test_breakpoint_rejects_interrupt_flag_instructions() {
+        let addr = VirtAddr::from_ptr(breakpoint_test_popfq as *const ());
+        assert_eq!(
+            set_breakpoint(addr),
+            Err(BreakpointError::UnsupportedInstruction(addr))
+        );
+        assert_eq!(hit_count(addr), None);
+    }
+}
@@ -1,11 +1,17 @@
-use x86_64::VirtAddr;
+use crate::memory::stack;
 use x86_64::structures::tss::TaskStateSegment;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+
+// IST 栈的大小（页数），每个栈下方还有一个未映射的保护页
+const IST_STACK_PAGES: u64 = 5;
 
 // 初始化全局描述符表
+//
+// IST 栈从内核虚拟地址空间分配，所以必须在内存管理和内核堆初始化之后调用。
 pub fn init() {
     use x86_64::instructions::tables::load_tss;
     use x86_64::instructions::segmentation::{CS, Segment};
@@ -37,14 +43,18 @@ lazy_static! {
     // 创建任务状态段
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
-        // 设置 TSS 的栈指针
+        // 设置 TSS 的栈指针，栈溢出时会碰到保护页而不是破坏其他内存
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5; // 栈大小
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE]; // 模拟栈存储区
-            let stack_start = VirtAddr::from_ptr(unsafe {&raw const STACK});
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
+            let stack = stack::allocate_stack("double fault", IST_STACK_PAGES)
+                .expect("failed to allocate double fault stack");
+            stack.top()
+        };
+        // NMI 可能在任何时刻到来，包括栈指针不可用的时候，使用独立的栈
+        tss.interrupt_stack_table[NMI_IST_INDEX as usize] = {
+            let stack = stack::allocate_stack("nmi", IST_STACK_PAGES)
+                .expect("failed to allocate nmi stack");
+            stack.top()
         };
         tss
     };
-}
\ No newline at end of file
+}
@@ -1,10 +1,19 @@
 use crate::{gdt, print,println};
+use crate::memory::{cow, demand, extable, stack};
+use deferred::Work;
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
 use crate::hlt_loop;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
+pub mod apic;
+pub mod deferred;
+pub mod exceptions;
+pub mod irq;
+pub mod nmi;
+pub mod stats;
+
 // -----------------
 // IDT 中断描述符表
 // -----------------
@@ -20,14 +29,16 @@ lazy_static!(
             idt.double_fault.set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX); // 设置双重故障异常的处理函数
         }
-        // 设置定时器中断的处理函数
-        idt[InterruptIndex::Timer.as_usize()]
-            .set_handler_fn(timer_interrupt_handler); // 定时器中断的处理函数
-        // Keyboard 中断的处理函数
-        idt[InterruptIndex::Keyboard.as_usize()]
-            .set_handler_fn(keyboard_interrupt_handler); // 键盘中断的处理函数
+        // 16 条 IRQ 线的分发入口，具体的处理函数通过 irq::register_irq 注册
+        irq::install(&mut idt);
+        apic::install(&mut idt);
+        crate::time::apic_timer::install(&mut idt);
         // 页错误异常的处理函数
         idt.page_fault.set_handler_fn(page_fault_handler); // 设置页错误异常的处理函数
+        // 其余的异常，避免它们升级为双重故障而丢失原始原因
+        exceptions::install(&mut idt);
+        // NMI 使用自己的入口代码和 IST 栈
+        nmi::install(&mut idt);
         idt
     };
 );
@@ -37,8 +48,24 @@ pub fn init_idt() {
     IDT.load(); // 加载 IDT
 }
 
+/// 注册内核自身的 IRQ 处理函数，选择中断控制器并屏蔽其余的 IRQ 线
+///
+/// 在 PIC 初始化之后、内存管理初始化之后调用，APIC 的寄存器需要映射到内核地址空间。
+pub fn init_irqs() {
+    let _ = irq::register_irq(irq::TIMER, timer_interrupt_handler)
+        .expect("failed to register timer handler");
+    let _ = irq::register_irq(irq::KEYBOARD, keyboard_interrupt_handler)
+        .expect("failed to register keyboard handler");
+    irq::init();
+}
+
 // 处理断点异常的函数
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn breakpoint_handler(mut stack_frame: InterruptStackFrame) {
+    stats::record(3);
+    // 通过 breakpoint::set_breakpoint 设置的断点：调用回调后单步执行原来的指令
+    if crate::breakpoint::handle_breakpoint(&mut stack_frame) {
+        return;
+    }
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
@@ -47,14 +74,33 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    use x86_64::registers::control::Cr2;
+
+    stats::record(8);
+    // 栈溢出时 CPU 无法压入缺页异常的栈帧，于是升级为双重故障，
+    // 此时 CR2 中是触发缺页的保护页地址
+    if let Some(name) = stack::guard_page_owner(Cr2::read()) {
+        panic!("EXCEPTION: DOUBLE FAULT\nstack overflow on stack {}\n{:#?}", name, stack_frame);
+    }
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame); // 打印异常信息并进入 panic 状态
 }
 
-// 处理键盘中断的函数
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+/// 键盘中断的下半部，参数为扫描码
+pub static KEYBOARD_WORK: Work = Work::new("keyboard", keyboard_work);
+
+// 处理键盘中断的函数，读出扫描码后交给下半部解码
+fn keyboard_interrupt_handler(_irq: u8) {
+    use x86_64::instructions::port::Port;
+
+    let mut port = Port::new(0x60); // 键盘输入端口 PS/2
+    let scancode: u8 = unsafe { port.read() }; // 必须读出扫描码，否则键盘不会再产生中断
+    let _ = KEYBOARD_WORK.schedule(u64::from(scancode)); // 队列已满时丢弃这次按键
+}
+
+// 解码扫描码并打印按键，在开中断时运行
+fn keyboard_work(scancode: u64) {
     use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
     use spin::Mutex;
-    use x86_64::instructions::port::Port;
 
     lazy_static! {
         static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
@@ -64,10 +110,7 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 
     let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(0x60); // 键盘输入端口 PS/2
-
-    let scancode: u8 = unsafe { port.read() }; // 读取键盘输入的扫描码
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+    if let Ok(Some(key_event)) = keyboard.add_byte(scancode as u8) {
         if let Some(key) = keyboard.process_keyevent(key_event) {
             match key {
                 DecodedKey::Unicode(character) => print!("{}", character),
@@ -75,20 +118,36 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
             }
         }
     }
-
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8()); // 通知 PIC 中断结束
-    }
 }
 
 // 处理页错误异常的函数
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     use x86_64::registers::control::Cr2;
 
+    stats::record(14);
+    // 按需分配区域中的缺页：映射一个清零的帧后继续执行
+    if demand::handle_page_fault(Cr2::read(), error_code) {
+        return;
+    }
+    // 写时复制页面上的写入：复制帧或恢复可写后继续执行
+    if cow::handle_page_fault(Cr2::read(), error_code) {
+        return;
+    }
+    // 异常表中的受保护访问：跳到修复代码，由访问函数返回错误
+    let fault = extable::Fault::PageFault { addr: Cr2::read(), error_code };
+    if extable::fixup(&mut stack_frame, fault) {
+        return;
+    }
+
+    if let Some(name) = stack::guard_page_owner(Cr2::read()) {
+        println!("EXCEPTION: PAGE FAULT\nstack overflow on stack {}", name);
+        println!("{:#?}", stack_frame);
+        hlt_loop();
+    }
+
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error Code: {:?}", error_code);
@@ -112,28 +171,7 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)] // 枚举值以 u8 存储
-pub enum InterruptIndex {
-    Timer = PIC_1_OFFSET,
-    Keyboard,
-}
-
-impl InterruptIndex {
-    fn as_u8(self) -> u8 {
-        self as u8
-    }
-
-    fn as_usize(self) -> usize {
-        usize::from(self.as_u8())
-    }
-}
-
 // 处理定时器中断的函数
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    print!(".");
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8()); // 通知 PIC 中断结束
-    }
+fn timer_interrupt_handler(_irq: u8) {
+    crate::time::tick();
 }
@@ -0,0 +1,338 @@
+use super::{PICS, PIC_1_OFFSET};
+use crate::acpi;
+use crate::memory::vmalloc;
+use alloc::vec::Vec;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Local APIC 的伪中断向量，低 4 位必须全为 1
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// 初始化 APIC 时可能出现的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    /// CPUID 报告处理器没有 APIC
+    NotSupported,
+    /// 没有找到 ACPI MADT
+    NoMadt,
+    /// MADT 中没有 I/O APIC
+    NoIoApic,
+    /// 无法映射 APIC 的寄存器
+    MapFailed,
+}
+
+/// 当前处理器的 Local APIC，通过内存映射的寄存器访问
+#[derive(Debug, Clone, Copy)]
+pub struct LocalApic {
+    base: VirtAddr,
+}
+
+impl LocalApic {
+    pub const ID: u32 = 0x20;
+    pub const VERSION: u32 = 0x30;
+    pub const TPR: u32 = 0x80;
+    pub const EOI: u32 = 0xb0;
+    pub const SVR: u32 = 0xf0;
+    pub const ISR: u32 = 0x100;
+    pub const LVT_TIMER: u32 = 0x320;
+    pub const LVT_PERFORMANCE: u32 = 0x340;
+    pub const LVT_LINT0: u32 = 0x350;
+    pub const LVT_LINT1: u32 = 0x360;
+    pub const LVT_ERROR: u32 = 0x370;
+    pub const TIMER_INITIAL_COUNT: u32 = 0x380;
+    pub const TIMER_CURRENT_COUNT: u32 = 0x390;
+    pub const TIMER_DIVIDE: u32 = 0x3e0;
+
+    /// 读取一个寄存器
+    pub fn read(&self, register: u32) -> u32 {
+        unsafe { (self.base + u64::from(register)).as_ptr::<u32>().read_volatile() }
+    }
+
+    /// 写入一个寄存器
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证写入的值不会破坏中断的投递。
+    pub unsafe fn write(&self, register: u32, value: u32) {
+        (self.base + u64::from(register)).as_mut_ptr::<u32>().write_volatile(value)
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(Self::ID) >> 24) as u8
+    }
+
+    /// 通知当前正在处理的中断已经结束
+    pub fn end_of_interrupt(&self) {
+        unsafe { self.write(Self::EOI, 0) };
+    }
+}
+
+// Local APIC 寄存器的虚拟地址，0 表示 APIC 未启用、仍在使用 PIC
+static LOCAL_APIC_BASE: AtomicU64 = AtomicU64::new(0);
+
+/// 返回当前处理器的 Local APIC，APIC 未启用时返回 None
+pub fn local_apic() -> Option<LocalApic> {
+    match LOCAL_APIC_BASE.load(Ordering::Acquire) {
+        0 => None,
+        base => Some(LocalApic {
+            base: VirtAddr::new(base),
+        }),
+    }
+}
+
+/// 中断是否由 APIC 投递
+pub fn is_active() -> bool {
+    local_apic().is_some()
+}
+
+// 一个 I/O APIC，通过 IOREGSEL 选择寄存器后从 IOWIN 读写
+struct IoApic {
+    base: VirtAddr,
+    gsi_base: u32,
+    entries: u32,
+}
+
+impl IoApic {
+    const VERSION: u32 = 0x01;
+    const REDIRECTION_TABLE: u32 = 0x10;
+
+    unsafe fn read(&self, register: u32) -> u32 {
+        self.base.as_mut_ptr::<u32>().write_volatile(register);
+        (self.base + 0x10u64).as_ptr::<u32>().read_volatile()
+    }
+
+    unsafe fn write(&self, register: u32, value: u32) {
+        self.base.as_mut_ptr::<u32>().write_volatile(register);
+        (self.base + 0x10u64).as_mut_ptr::<u32>().write_volatile(value);
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.entries
+    }
+
+    fn redirection(&self, gsi: u32) -> u64 {
+        let register = Self::REDIRECTION_TABLE + 2 * (gsi - self.gsi_base);
+        unsafe { u64::from(self.read(register)) | u64::from(self.read(register + 1)) << 32 }
+    }
+
+    fn set_redirection(&self, gsi: u32, entry: u64) {
+        let register = Self::REDIRECTION_TABLE + 2 * (gsi - self.gsi_base);
+        unsafe {
+            // 先写入包含屏蔽位的低 32 位，避免中途以不完整的设置投递中断
+            self.write(register, entry as u32 | REDIRECTION_MASKED as u32);
+            self.write(register + 1, (entry >> 32) as u32);
+            self.write(register, entry as u32);
+        }
+    }
+}
+
+const REDIRECTION_NMI: u64 = 0b100 << 8;
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+// 一条传统 IRQ 线被连接到的全局系统中断及其电气特性
+#[derive(Debug, Clone, Copy)]
+struct Route {
+    gsi: u32,
+    active_low: bool,
+    level_triggered: bool,
+}
+
+struct IoApics {
+    io_apics: Vec<IoApic>,
+    routes: [Route; 16],
+}
+
+impl IoApics {
+    fn for_gsi(&self, gsi: u32) -> Option<&IoApic> {
+        self.io_apics.iter().find(|io_apic| io_apic.handles(gsi))
+    }
+}
+
+// 只在关中断时访问
+static IO_APICS: Mutex<Option<IoApics>> = Mutex::new(None);
+
+fn cpu_has_apic() -> bool {
+    let features = __cpuid(1);
+    features.edx & (1 << 9) != 0
+}
+
+/// 启用 Local APIC 和 I/O APIC，屏蔽传统 PIC，并把 16 条 IRQ 线路由到 I/O APIC
+///
+/// IRQ `n` 使用与 PIC 相同的向量 `PIC_1_OFFSET + n`，`masked(n)` 为 true 的线保持屏蔽。
+/// 中断源覆盖（例如 IRQ 0 连接到 GSI 2）会被遵守。失败时什么也不修改。
+pub fn init(masked: impl Fn(u8) -> bool) -> Result<(), ApicError> {
+    if !cpu_has_apic() {
+        return Err(ApicError::NotSupported);
+    }
+    let madt = acpi::madt().ok_or(ApicError::NoMadt)?;
+    if madt.io_apics.is_empty() {
+        return Err(ApicError::NoIoApic);
+    }
+
+    let local_base = vmalloc::ioremap(PhysAddr::new(madt.local_apic_address), 4096)
+        .ok_or(ApicError::MapFailed)?;
+    let mut io_apics: Vec<IoApic> = Vec::new();
+    for entry in &madt.io_apics {
+        let Some(base) = vmalloc::ioremap(PhysAddr::new(u64::from(entry.address)), 4096) else {
+            // 释放已经映射的区域，保证失败时什么也不修改
+            vmalloc::vfree(local_base);
+            for io_apic in &io_apics {
+                vmalloc::vfree(io_apic.base);
+            }
+            return Err(ApicError::MapFailed);
+        };
+        let mut io_apic = IoApic {
+            base,
+            gsi_base: entry.gsi_base,
+            entries: 0,
+        };
+        io_apic.entries = ((unsafe { io_apic.read(IoApic::VERSION) } >> 16) & 0xff) + 1;
+        io_apics.push(io_apic);
+    }
+
+    // ISA IRQ 默认直接连接到同号的 GSI，边沿触发、高电平有效
+    let mut routes = [Route {
+        gsi: 0,
+        active_low: false,
+        level_triggered: false,
+    }; 16];
+    for (irq, route) in routes.iter_mut().enumerate() {
+        route.gsi = irq as u32;
+    }
+    for iso in madt.overrides.iter().filter(|iso| iso.bus == 0) {
+        if let Some(route) = routes.get_mut(usize::from(iso.source)) {
+            *route = Route {
+                gsi: iso.gsi,
+                active_low: iso.active_low(),
+                level_triggered: iso.level_triggered(),
+            };
+        }
+    }
+
+    let local_apic = LocalApic { base: local_base };
+    unsafe {
+        // 启用 Local APIC：设置 APIC_BASE MSR 的全局使能位和伪中断向量寄存器的软件使能位
+        let mut apic_base = Msr::new(IA32_APIC_BASE);
+        apic_base.write(apic_base.read() | APIC_BASE_ENABLE);
+        local_apic.write(LocalApic::SVR, 0x100 | u32::from(SPURIOUS_VECTOR));
+        local_apic.write(LocalApic::TPR, 0);
+        // 不再使用 PIC，屏蔽它的所有线；它仍然映射在 32-47，偶尔出现的伪中断不会被当作异常
+        PICS.lock().write_masks(0xff, 0xff);
+    }
+
+    let io_apics = IoApics { io_apics, routes };
+    let destination = u64::from(local_apic.id()) << 56;
+    for irq in 0..16u8 {
+        if irq == 2 {
+            continue; // 级联线在 APIC 模式下不存在
+        }
+        let route = io_apics.routes[usize::from(irq)];
+        if let Some(io_apic) = io_apics.for_gsi(route.gsi) {
+            let mut entry = u64::from(PIC_1_OFFSET + irq) | destination;
+            if route.active_low {
+                entry |= REDIRECTION_ACTIVE_LOW;
+            }
+            if route.level_triggered {
+                entry |= REDIRECTION_LEVEL;
+            }
+            if masked(irq) {
+                entry |= REDIRECTION_MASKED;
+            }
+            io_apic.set_redirection(route.gsi, entry);
+        }
+    }
+    *IO_APICS.lock() = Some(io_apics);
+    LOCAL_APIC_BASE.store(local_base.as_u64(), Ordering::Release);
+    Ok(())
+}
+
+/// 在 I/O APIC 上屏蔽或取消屏蔽传统 IRQ 线 `irq`
+pub fn set_irq_masked(irq: u8, masked: bool) {
+    let io_apics = IO_APICS.lock();
+    let io_apics = match io_apics.as_ref() {
+        Some(io_apics) => io_apics,
+        None => return,
+    };
+    let gsi = io_apics.routes[usize::from(irq)].gsi;
+    if let Some(io_apic) = io_apics.for_gsi(gsi) {
+        let entry = io_apic.redirection(gsi);
+        let entry = if masked {
+            entry | REDIRECTION_MASKED
+        } else {
+            entry & !REDIRECTION_MASKED
+        };
+        io_apic.set_redirection(gsi, entry);
+    }
+}
+
+/// 传统 IRQ 线 `irq` 在 I/O APIC 上是否被屏蔽
+pub fn is_irq_masked(irq: u8) -> bool {
+    let io_apics = IO_APICS.lock();
+    io_apics.as_ref().is_none_or(|io_apics| {
+        let gsi = io_apics.routes[usize::from(irq)].gsi;
+        io_apics
+            .for_gsi(gsi)
+            .is_none_or(|io_apic| io_apic.redirection(gsi) & REDIRECTION_MASKED != 0)
+    })
+}
+
+/// 传统 IRQ 线 `irq` 连接到的全局系统中断
+pub fn gsi_for_irq(irq: u8) -> Option<u32> {
+    let io_apics = IO_APICS.lock();
+    io_apics.as_ref().map(|io_apics| io_apics.routes[usize::from(irq)].gsi)
+}
+
+/// 全局系统中断 `gsi` 是否连接着某条传统 IRQ 线
+pub fn is_legacy_gsi(gsi: u32) -> bool {
+    let io_apics = IO_APICS.lock();
+    io_apics
+        .as_ref()
+        .is_some_and(|io_apics| io_apics.routes.iter().any(|route| route.gsi == gsi))
+}
+
+/// 把全局系统中断 `gsi` 以边沿触发的 NMI 投递到当前处理器
+///
+/// 没有 I/O APIC 处理这个 GSI 时返回 false。
+pub fn route_gsi_as_nmi(gsi: u32) -> bool {
+    let Some(local_apic) = local_apic() else {
+        return false;
+    };
+    let io_apics = IO_APICS.lock();
+    match io_apics.as_ref().and_then(|io_apics| io_apics.for_gsi(gsi)) {
+        Some(io_apic) => {
+            let destination = u64::from(local_apic.id()) << 56;
+            io_apic.set_redirection(gsi, REDIRECTION_NMI | destination);
+            true
+        }
+        None => false,
+    }
+}
+
+// Local APIC 的伪中断不需要 EOI
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    super::stats::record(SPURIOUS_VECTOR);
+}
+
+/// 在 IDT 中设置 Local APIC 伪中断的处理函数
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    idt[usize::from(SPURIOUS_VECTOR)].set_handler_fn(spurious_interrupt_handler);
+}
+
+#[test_case]
+fn test_apic_routes_timer_and_keyboard() {
+    // QEMU 提供 APIC，初始化后定时器和键盘应当经过 I/O APIC 投递
+    assert!(is_active());
+    assert!(!is_irq_masked(super::irq::TIMER));
+    assert!(!is_irq_masked(super::irq::KEYBOARD));
+    // QEMU 的 MADT 把 IRQ 0 覆盖到 GSI 2
+    assert_eq!(gsi_for_irq(super::irq::TIMER), Some(2));
+}
@@ -0,0 +1,217 @@
+use crate::time::tsc;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// 延迟工作队列的容量
+pub const QUEUE_CAPACITY: usize = 256;
+
+/// 中断处理函数排队、在中断返回之前开中断执行的工作项（下半部）
+///
+/// 工作项一般定义为静态变量，同一个工作项可以带着不同的参数多次排队。
+pub struct Work {
+    name: &'static str,
+    func: fn(u64),
+    runs: AtomicU64,
+    dropped: AtomicU64,
+    // 从排队到开始执行经过的 TSC 周期
+    total_latency: AtomicU64,
+    max_latency: AtomicU64,
+}
+
+/// 工作项的执行次数和排队延迟
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub runs: u64,
+    /// 因为队列已满而被丢弃的次数
+    pub dropped: u64,
+    pub average: Duration,
+    pub max: Duration,
+}
+
+/// 队列已满，工作项没有排队
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+impl Work {
+    pub const fn new(name: &'static str, func: fn(u64)) -> Self {
+        Work {
+            name,
+            func,
+            runs: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            total_latency: AtomicU64::new(0),
+            max_latency: AtomicU64::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// 带着参数 `arg` 排队，可以在中断上下文中调用
+    pub fn schedule(&'static self, arg: u64) -> Result<(), QueueFull> {
+        let entry = Entry {
+            work: self,
+            arg,
+            queued_at: tsc::read(),
+        };
+        let result = interrupts::without_interrupts(|| QUEUE.lock().push(entry));
+        if result.is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// 当前的执行次数和排队延迟
+    pub fn stats(&self) -> LatencyStats {
+        let runs = self.runs.load(Ordering::Relaxed);
+        let total = self.total_latency.load(Ordering::Relaxed);
+        LatencyStats {
+            runs,
+            dropped: self.dropped.load(Ordering::Relaxed),
+            average: tsc::to_duration(total.checked_div(runs).unwrap_or(0)),
+            max: tsc::to_duration(self.max_latency.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn run(&self, arg: u64, queued_at: u64) {
+        let latency = tsc::read().saturating_sub(queued_at);
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.total_latency.fetch_add(latency, Ordering::Relaxed);
+        self.max_latency.fetch_max(latency, Ordering::Relaxed);
+        (self.func)(arg);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    work: &'static Work,
+    arg: u64,
+    queued_at: u64,
+}
+
+// 固定容量的环形队列，在中断上下文中排队时不需要分配内存
+struct Queue {
+    entries: [Option<Entry>; QUEUE_CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+impl Queue {
+    fn push(&mut self, entry: Entry) -> Result<(), QueueFull> {
+        if self.len == QUEUE_CAPACITY {
+            return Err(QueueFull);
+        }
+        self.entries[(self.head + self.len) % QUEUE_CAPACITY] = Some(entry);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Entry> {
+        if self.len == 0 {
+            return None;
+        }
+        let entry = self.entries[self.head].take();
+        self.head = (self.head + 1) % QUEUE_CAPACITY;
+        self.len -= 1;
+        entry
+    }
+}
+
+// 只在关中断时访问
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    entries: [None; QUEUE_CAPACITY],
+    head: 0,
+    len: 0,
+});
+// 正在执行工作项，嵌套的中断不再重复执行
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// 队列中等待执行的工作项数量
+pub fn pending() -> usize {
+    interrupts::without_interrupts(|| QUEUE.lock().len)
+}
+
+// 依次执行队列中的工作项，进入和返回时都是关中断的。
+// 在关中断时检查队列，所以队列为空后返回时不会漏掉刚排队的工作项。
+fn run_queue() {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    loop {
+        let entry = QUEUE.lock().pop();
+        let Some(entry) = entry else { break };
+        interrupts::enable();
+        entry.work.run(entry.arg, entry.queued_at);
+        interrupts::disable();
+    }
+    RUNNING.store(false, Ordering::Release);
+}
+
+/// 由中断处理函数在发送 EOI 之后调用，开中断执行排队的工作项
+pub(crate) fn irq_exit() {
+    run_queue();
+}
+
+/// 在普通上下文中执行排队的工作项，例如空闲循环
+pub fn run_pending() {
+    interrupts::without_interrupts(run_queue);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::delay_ms;
+
+    static LAST_ARG: AtomicU64 = AtomicU64::new(0);
+
+    fn record_arg(arg: u64) {
+        // 工作项在开中断时执行
+        assert!(interrupts::are_enabled());
+        LAST_ARG.store(arg, Ordering::Relaxed);
+    }
+
+    #[test_case]
+    fn test_work_runs_after_interrupt() {
+        static WORK: Work = Work::new("test", record_arg);
+        WORK.schedule(42).unwrap();
+        delay_ms(5);
+        assert_eq!(LAST_ARG.load(Ordering::Relaxed), 42);
+        let stats = WORK.stats();
+        assert_eq!(stats.runs, 1);
+        assert!(stats.max >= stats.average);
+    }
+
+    #[test_case]
+    fn test_work_can_wait_for_ticks() {
+        static DONE: AtomicBool = AtomicBool::new(false);
+        fn wait(_arg: u64) {
+            // 时钟计数在中断中更新，下半部中也可以等待
+            delay_ms(2);
+            DONE.store(true, Ordering::Relaxed);
+        }
+        static WORK: Work = Work::new("wait", wait);
+        WORK.schedule(0).unwrap();
+        delay_ms(20);
+        assert!(DONE.load(Ordering::Relaxed));
+    }
+
+    #[test_case]
+    fn test_full_queue_drops_work() {
+        fn nothing(_arg: u64) {}
+        static WORK: Work = Work::new("flood", nothing);
+        let queued = interrupts::without_interrupts(|| {
+            let mut queued = 0;
+            while WORK.schedule(0).is_ok() {
+                queued += 1;
+            }
+            queued
+        });
+        assert!(queued <= QUEUE_CAPACITY);
+        assert_eq!(WORK.stats().dropped, 1);
+        run_pending();
+        assert_eq!(WORK.stats().runs, queued as u64);
+    }
+}
@@ -0,0 +1,280 @@
+use super::stats;
+use crate::memory::extable::{self, Fault};
+use crate::println;
+use crate::serial::_print_emergency;
+use core::fmt;
+use spin::Mutex;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, SelectorErrorCode};
+use x86_64::VirtAddr;
+
+/// 处理器定义的异常向量的助记符和名称
+pub fn exception_name(vector: u8) -> Option<(&'static str, &'static str)> {
+    let name = match vector {
+        0 => ("#DE", "DIVIDE ERROR"),
+        1 => ("#DB", "DEBUG"),
+        2 => ("NMI", "NON-MASKABLE INTERRUPT"),
+        3 => ("#BP", "BREAKPOINT"),
+        4 => ("#OF", "OVERFLOW"),
+        5 => ("#BR", "BOUND RANGE EXCEEDED"),
+        6 => ("#UD", "INVALID OPCODE"),
+        7 => ("#NM", "DEVICE NOT AVAILABLE"),
+        8 => ("#DF", "DOUBLE FAULT"),
+        10 => ("#TS", "INVALID TSS"),
+        11 => ("#NP", "SEGMENT NOT PRESENT"),
+        12 => ("#SS", "STACK-SEGMENT FAULT"),
+        13 => ("#GP", "GENERAL PROTECTION FAULT"),
+        14 => ("#PF", "PAGE FAULT"),
+        16 => ("#MF", "X87 FLOATING-POINT EXCEPTION"),
+        17 => ("#AC", "ALIGNMENT CHECK"),
+        18 => ("#MC", "MACHINE CHECK"),
+        19 => ("#XM", "SIMD FLOATING-POINT EXCEPTION"),
+        20 => ("#VE", "VIRTUALIZATION EXCEPTION"),
+        21 => ("#CP", "CONTROL PROTECTION EXCEPTION"),
+        28 => ("#HV", "HYPERVISOR INJECTION EXCEPTION"),
+        29 => ("#VC", "VMM COMMUNICATION EXCEPTION"),
+        30 => ("#SX", "SECURITY EXCEPTION"),
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// 解码后的异常错误码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// 该异常没有错误码
+    None,
+    /// #TS、#NP、#SS 和 #GP 的错误码，引用出错的段选择子
+    Selector(SelectorErrorCode),
+    /// #CP 的错误码
+    ControlProtection(u64),
+    /// 其他异常的原始错误码
+    Raw(u64),
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorCode::None => write!(f, "no error code"),
+            ErrorCode::Selector(code) if code.is_null() => write!(f, "error code 0"),
+            ErrorCode::Selector(code) => write!(
+                f,
+                "selector index {} in {:?}{}",
+                code.index(),
+                code.descriptor_table(),
+                if code.external() { " (external event)" } else { "" }
+            ),
+            ErrorCode::ControlProtection(code) => {
+                let cause = match code & 0x7fff {
+                    1 => "NEAR-RET",
+                    2 => "FAR-RET/IRET",
+                    3 => "ENDBRANCH",
+                    4 => "RSTORSSP",
+                    5 => "SETSSBSY",
+                    _ => "unknown",
+                };
+                write!(f, "control protection {}", cause)?;
+                if code & (1 << 15) != 0 {
+                    write!(f, " (in enclave)")?;
+                }
+                Ok(())
+            }
+            ErrorCode::Raw(code) => write!(f, "error code {:#x}", code),
+        }
+    }
+}
+
+/// 一次异常的信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionInfo {
+    pub vector: u8,
+    pub error_code: ErrorCode,
+    /// 触发异常的指令地址（陷阱类异常为下一条指令的地址）
+    pub instruction_pointer: VirtAddr,
+}
+
+impl ExceptionInfo {
+    pub fn mnemonic(&self) -> &'static str {
+        exception_name(self.vector).map_or("?", |(mnemonic, _)| mnemonic)
+    }
+
+    pub fn name(&self) -> &'static str {
+        exception_name(self.vector).map_or("UNKNOWN EXCEPTION", |(_, name)| name)
+    }
+}
+
+impl fmt::Display for ExceptionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "EXCEPTION: {} ({}) at {:#x}, {}",
+            self.name(),
+            self.mnemonic(),
+            self.instruction_pointer.as_u64(),
+            self.error_code
+        )
+    }
+}
+
+/// 异常钩子，返回 `true` 表示异常已被处理，处理函数直接返回到（可能被修改过的）栈帧
+pub type ExceptionHook = fn(&ExceptionInfo, &mut InterruptStackFrame) -> bool;
+
+static HOOK: Mutex<Option<ExceptionHook>> = Mutex::new(None);
+
+/// 设置在报告异常之前调用的钩子，调试器和测试可以借此从异常中恢复
+pub fn set_exception_hook(hook: Option<ExceptionHook>) {
+    *HOOK.lock() = hook;
+}
+
+fn call_hook(info: &ExceptionInfo, stack_frame: &mut InterruptStackFrame) -> bool {
+    let hook = *HOOK.lock();
+    hook.is_some_and(|hook| hook(info, stack_frame))
+}
+
+// 钩子没有处理时，以 panic 的形式报告致命异常
+fn report(vector: u8, error_code: ErrorCode, stack_frame: &mut InterruptStackFrame) {
+    stats::record(vector);
+    let info = ExceptionInfo {
+        vector,
+        error_code,
+        instruction_pointer: stack_frame.instruction_pointer,
+    };
+    if !call_hook(&info, stack_frame) {
+        panic!("{}\n{:#?}", info, stack_frame);
+    }
+}
+
+// 通知非致命的异常，钩子没有处理时打印后继续执行
+fn notify(vector: u8, stack_frame: &mut InterruptStackFrame) {
+    stats::record(vector);
+    let info = ExceptionInfo {
+        vector,
+        error_code: ErrorCode::None,
+        instruction_pointer: stack_frame.instruction_pointer,
+    };
+    if !call_hook(&info, stack_frame) {
+        println!("{}\n{:#?}", info, stack_frame);
+    }
+}
+
+// 通知不属于看门狗的 NMI。NMI 可能打断持有任何锁的代码，所以只尝试获取钩子的锁，
+// 并通过紧急串口输出，不使用 VGA 的 WRITER
+pub(super) fn notify_nmi(stack_frame: &mut InterruptStackFrame) {
+    stats::record(2);
+    let info = ExceptionInfo {
+        vector: 2,
+        error_code: ErrorCode::None,
+        instruction_pointer: stack_frame.instruction_pointer,
+    };
+    let hook = HOOK.try_lock().and_then(|hook| *hook);
+    if !hook.is_some_and(|hook| hook(&info, stack_frame)) {
+        _print_emergency(format_args!("{}\n{:#?}\n", info, stack_frame));
+    }
+}
+
+// 为没有错误码的异常生成处理函数
+macro_rules! handler {
+    ($name:ident, $vector:expr) => {
+        extern "x86-interrupt" fn $name(mut stack_frame: InterruptStackFrame) {
+            report($vector, ErrorCode::None, &mut stack_frame);
+        }
+    };
+    ($name:ident, $vector:expr, $decode:expr) => {
+        extern "x86-interrupt" fn $name(mut stack_frame: InterruptStackFrame, error_code: u64) {
+            report($vector, $decode(error_code), &mut stack_frame);
+        }
+    };
+}
+
+fn selector(error_code: u64) -> ErrorCode {
+    ErrorCode::Selector(SelectorErrorCode::new_truncate(error_code))
+}
+
+handler!(divide_error_handler, 0);
+handler!(overflow_handler, 4);
+handler!(bound_range_exceeded_handler, 5);
+handler!(invalid_opcode_handler, 6);
+handler!(device_not_available_handler, 7);
+handler!(invalid_tss_handler, 10, selector);
+handler!(segment_not_present_handler, 11, selector);
+handler!(stack_segment_fault_handler, 12, selector);
+handler!(x87_floating_point_handler, 16);
+handler!(alignment_check_handler, 17, ErrorCode::Raw);
+handler!(simd_floating_point_handler, 19);
+handler!(virtualization_handler, 20);
+handler!(cp_protection_handler, 21, ErrorCode::ControlProtection);
+handler!(hv_injection_handler, 28);
+handler!(vmm_communication_handler, 29, ErrorCode::Raw);
+handler!(security_exception_handler, 30, ErrorCode::Raw);
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+    mut stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    // 异常表中的受保护访问（例如访问非规范地址）：跳到修复代码
+    if extable::fixup(&mut stack_frame, Fault::GeneralProtection { error_code }) {
+        stats::record(13);
+        return;
+    }
+    report(13, selector(error_code), &mut stack_frame);
+}
+
+extern "x86-interrupt" fn debug_handler(mut stack_frame: InterruptStackFrame) {
+    // 断点恢复后的单步：重新写入断点
+    if crate::breakpoint::handle_single_step(&mut stack_frame) {
+        stats::record(1);
+        return;
+    }
+    notify(1, &mut stack_frame);
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    stats::record(18);
+    // 机器检查之后无法安全地继续执行，不调用钩子
+    let info = ExceptionInfo {
+        vector: 18,
+        error_code: ErrorCode::None,
+        instruction_pointer: stack_frame.instruction_pointer,
+    };
+    panic!("{}\n{:#?}", info, stack_frame);
+}
+
+/// 为断点、NMI、双重故障和缺页之外的所有异常设置处理函数
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available.set_handler_fn(device_not_available_handler);
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+    idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.cp_protection_exception.set_handler_fn(cp_protection_handler);
+    idt.hv_injection_exception.set_handler_fn(hv_injection_handler);
+    idt.vmm_communication_exception.set_handler_fn(vmm_communication_handler);
+    idt.security_exception.set_handler_fn(security_exception_handler);
+}
+
+#[test_case]
+fn test_decode_error_codes() {
+    use alloc::format;
+
+    // GDT 中下标为 5 的选择子
+    assert_eq!(format!("{}", selector(5 << 3)), "selector index 5 in Gdt");
+    // IDT 中下标为 13 的选择子，由外部事件引起
+    assert_eq!(
+        format!("{}", selector((13 << 3) | 0b011)),
+        "selector index 13 in Idt (external event)"
+    );
+    assert_eq!(format!("{}", selector(0)), "error code 0");
+    assert_eq!(
+        format!("{}", ErrorCode::ControlProtection(3)),
+        "control protection ENDBRANCH"
+    );
+}
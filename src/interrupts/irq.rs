@@ -0,0 +1,290 @@
+use super::{apic, deferred, stats, PICS, PIC_1_OFFSET};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+/// 传统 PIC 提供的 IRQ 线数量
+pub const IRQ_COUNT: usize = 16;
+/// 每条 IRQ 线最多共享的处理函数数量
+pub const MAX_SHARED_HANDLERS: usize = 4;
+
+pub const TIMER: u8 = 0;
+pub const KEYBOARD: u8 = 1;
+// 从 PIC 级联在主 PIC 的 IRQ 2 上
+const CASCADE: u8 = 2;
+
+/// IRQ 处理函数，参数为触发的 IRQ 号
+///
+/// 处理函数在关中断的中断上下文中运行，不需要也不应该自己发送 EOI；
+/// 耗时的工作应当通过 `deferred::Work` 交给下半部。
+pub type IrqHandler = fn(irq: u8);
+
+/// 注册 IRQ 处理函数时可能出现的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// IRQ 号超出范围
+    InvalidIrq(u8),
+    /// 该 IRQ 线上的处理函数已满
+    TooManyHandlers(u8),
+}
+
+/// 已注册的处理函数，用于取消注册
+#[derive(Debug, PartialEq, Eq)]
+#[must_use = "dropping the handle makes the handler impossible to unregister"]
+pub struct IrqHandle {
+    irq: u8,
+    slot: usize,
+}
+
+impl IrqHandle {
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+}
+
+type Slots = [Option<IrqHandler>; MAX_SHARED_HANDLERS];
+
+// 每条 IRQ 线上注册的处理函数，只在关中断时访问，避免与中断处理函数死锁
+static HANDLERS: Mutex<[Slots; IRQ_COUNT]> = Mutex::new([[None; MAX_SHARED_HANDLERS]; IRQ_COUNT]);
+
+/// 为 `irq` 注册一个处理函数，同一条线上可以注册多个处理函数
+///
+/// 这条线上的第一个处理函数注册后，该线在 PIC 上被取消屏蔽。
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Result<IrqHandle, IrqError> {
+    if usize::from(irq) >= IRQ_COUNT {
+        return Err(IrqError::InvalidIrq(irq));
+    }
+    without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let slots = &mut handlers[usize::from(irq)];
+        let slot = slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(IrqError::TooManyHandlers(irq))?;
+        slots[slot] = Some(handler);
+        set_masked(irq, false);
+        Ok(IrqHandle { irq, slot })
+    })
+}
+
+/// 取消注册处理函数，这条线上没有处理函数后重新屏蔽它
+pub fn unregister_irq(handle: IrqHandle) {
+    without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let slots = &mut handlers[usize::from(handle.irq)];
+        slots[handle.slot] = None;
+        if slots.iter().all(Option::is_none) {
+            set_masked(handle.irq, true);
+        }
+    });
+}
+
+/// `irq` 上注册的处理函数数量
+pub fn handler_count(irq: u8) -> usize {
+    without_interrupts(|| {
+        HANDLERS.lock()[usize::from(irq)]
+            .iter()
+            .filter(|slot| slot.is_some())
+            .count()
+    })
+}
+
+/// 投递传统 IRQ 的中断控制器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptController {
+    /// 级联的 8259 PIC
+    Pic,
+    /// Local APIC 和 I/O APIC
+    Apic,
+}
+
+/// 当前使用的中断控制器
+pub fn controller() -> InterruptController {
+    if apic::is_active() {
+        InterruptController::Apic
+    } else {
+        InterruptController::Pic
+    }
+}
+
+// 屏蔽或取消屏蔽一条 IRQ 线
+fn set_masked(irq: u8, masked: bool) {
+    if apic::is_active() {
+        apic::set_irq_masked(irq, masked);
+        return;
+    }
+    let mut pics = PICS.lock();
+    let mut masks = unsafe { pics.read_masks() };
+    let (chip, bit) = (usize::from(irq / 8), irq % 8);
+    if masked {
+        masks[chip] |= 1 << bit;
+    } else {
+        masks[chip] &= !(1 << bit);
+        if chip == 1 {
+            masks[0] &= !(1 << CASCADE); // 从 PIC 的中断要经过级联线
+        }
+    }
+    unsafe { pics.write_masks(masks[0], masks[1]) };
+}
+
+/// `irq` 是否被屏蔽
+pub fn is_masked(irq: u8) -> bool {
+    if apic::is_active() {
+        return apic::is_irq_masked(irq);
+    }
+    // 与中断处理函数中的 EOI 共用 PICS 的锁，持有时必须关中断
+    let masks = without_interrupts(|| unsafe { PICS.lock().read_masks() });
+    masks[usize::from(irq / 8)] & (1 << (irq % 8)) != 0
+}
+
+/// 选择中断控制器并屏蔽所有没有处理函数的 IRQ 线，在 PIC 初始化之后调用
+///
+/// 优先使用 ACPI MADT 中描述的 APIC，找不到时继续使用 PIC。
+pub fn init() {
+    without_interrupts(|| {
+        let handlers = *HANDLERS.lock();
+        let unused = |irq: u8| handlers[usize::from(irq)].iter().all(Option::is_none);
+        if apic::init(unused).is_ok() {
+            return;
+        }
+
+        let mut masks = [0xffu8; 2];
+        for irq in 0..IRQ_COUNT as u8 {
+            if !unused(irq) {
+                masks[usize::from(irq / 8)] &= !(1 << (irq % 8));
+            }
+        }
+        if masks[1] != 0xff {
+            masks[0] &= !(1 << CASCADE);
+        }
+        unsafe { PICS.lock().write_masks(masks[0], masks[1]) };
+    });
+}
+
+// 8259 PIC 的命令端口，以及读取 ISR 的 OCW3 命令和 EOI 命令
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xa0;
+const READ_ISR: u8 = 0x0b;
+const PIC_EOI: u8 = 0x20;
+
+// 8259 PIC 产生的伪中断次数
+static SPURIOUS: AtomicU64 = AtomicU64::new(0);
+
+/// 8259 PIC 产生的伪 IRQ 7/15 的次数
+pub fn spurious_count() -> u64 {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+// 读取两片 PIC 的 ISR（正在服务的中断），低 8 位属于主 PIC
+fn pic_in_service() -> u16 {
+    let _pics = PICS.lock(); // 与其他 PIC 操作互斥
+    let mut master: Port<u8> = Port::new(PIC_1_COMMAND);
+    let mut slave: Port<u8> = Port::new(PIC_2_COMMAND);
+    unsafe {
+        master.write(READ_ISR);
+        slave.write(READ_ISR);
+        u16::from(master.read()) | u16::from(slave.read()) << 8
+    }
+}
+
+// IRQ 线上的信号在 PIC 应答之前消失时，PIC 以最低优先级的 IRQ 7（从 PIC 为 15）
+// 投递一个伪中断，此时 ISR 中对应的位没有置位
+fn is_spurious(irq: u8, in_service: u16) -> bool {
+    (irq == 7 || irq == 15) && in_service & (1 << irq) == 0
+}
+
+// 检查并处理 8259 PIC 的伪中断，返回 true 表示这次中断应当被忽略
+fn handle_spurious(irq: u8) -> bool {
+    if apic::is_active() || (irq != 7 && irq != 15) || !is_spurious(irq, pic_in_service()) {
+        return false;
+    }
+    SPURIOUS.fetch_add(1, Ordering::Relaxed);
+    if irq == 15 {
+        // 主 PIC 确实通过级联线收到了中断，只向它发送 EOI；伪 IRQ 7 不需要 EOI
+        unsafe { Port::<u8>::new(PIC_1_COMMAND).write(PIC_EOI) };
+    }
+    true
+}
+
+// 通知中断控制器当前中断已处理完毕
+fn end_of_interrupt(irq: u8) {
+    match apic::local_apic() {
+        Some(local_apic) => local_apic.end_of_interrupt(),
+        None => unsafe { PICS.lock().notify_end_of_interrupt(PIC_1_OFFSET + irq) },
+    }
+}
+
+// 依次调用这条线上的所有处理函数，统一发送 EOI 后执行排队的下半部
+fn dispatch(irq: u8) {
+    // 伪中断只计入 SPU 一行
+    if handle_spurious(irq) {
+        return;
+    }
+    stats::record(PIC_1_OFFSET + irq);
+    // 复制一份处理函数，调用期间不持有锁，处理函数中可以注册或取消注册
+    let slots = HANDLERS.lock()[usize::from(irq)];
+    for handler in slots.iter().flatten() {
+        handler(irq);
+    }
+    end_of_interrupt(irq);
+    deferred::irq_exit();
+}
+
+// 为每条 IRQ 线生成一个中断入口
+macro_rules! irq_stubs {
+    ($($irq:literal => $name:ident),* $(,)?) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: InterruptStackFrame) {
+                dispatch($irq);
+            }
+        )*
+
+        /// 在 IDT 中为 16 条 IRQ 线设置分发入口
+        pub fn install(idt: &mut InterruptDescriptorTable) {
+            $(idt[usize::from(PIC_1_OFFSET + $irq)].set_handler_fn($name);)*
+        }
+    };
+}
+
+irq_stubs! {
+    0 => irq0, 1 => irq1, 2 => irq2, 3 => irq3,
+    4 => irq4, 5 => irq5, 6 => irq6, 7 => irq7,
+    8 => irq8, 9 => irq9, 10 => irq10, 11 => irq11,
+    12 => irq12, 13 => irq13, 14 => irq14, 15 => irq15,
+}
+
+#[test_case]
+fn test_register_and_unregister_shared_irq() {
+    fn first(_irq: u8) {}
+    fn second(_irq: u8) {}
+    // IRQ 5 在 QEMU 中通常没有设备
+    let irq = 5;
+    assert!(is_masked(irq));
+    let a = register_irq(irq, first).unwrap();
+    let b = register_irq(irq, second).unwrap();
+    assert_eq!(handler_count(irq), 2);
+    assert!(!is_masked(irq));
+    unregister_irq(a);
+    assert!(!is_masked(irq));
+    unregister_irq(b);
+    assert_eq!(handler_count(irq), 0);
+    assert!(is_masked(irq));
+}
+
+#[test_case]
+fn test_register_rejects_invalid_irq() {
+    fn handler(_irq: u8) {}
+    assert_eq!(register_irq(16, handler), Err(IrqError::InvalidIrq(16)));
+}
+
+#[test_case]
+fn test_spurious_irq_detection() {
+    // ISR 中没有置位的 IRQ 7/15 是伪中断，其他 IRQ 不做判断
+    assert!(is_spurious(7, 0));
+    assert!(!is_spurious(7, 1 << 7));
+    assert!(is_spurious(15, 1 << CASCADE));
+    assert!(!is_spurious(15, 1 << 15 | 1 << CASCADE));
+    assert!(!is_spurious(1, 0));
+}
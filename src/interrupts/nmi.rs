@@ -0,0 +1,111 @@
+use super::{exceptions, stats};
+use crate::{gdt, watchdog};
+use core::arch::global_asm;
+use core::fmt;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::VirtAddr;
+
+/// NMI 打断的上下文：入口代码保存的通用寄存器和 CPU 压入的中断栈帧
+#[derive(Debug)]
+#[repr(C)]
+pub struct Registers {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub frame: InterruptStackFrame,
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame = &self.frame;
+        writeln!(
+            f,
+            "RIP: {:#018x} RSP: {:#018x} RFLAGS: {:#010x}",
+            frame.instruction_pointer.as_u64(),
+            frame.stack_pointer.as_u64(),
+            frame.cpu_flags
+        )?;
+        writeln!(f, "CS: {:#06x} SS: {:#06x}", frame.code_segment, frame.stack_segment)?;
+        writeln!(f, "RAX: {:#018x} RBX: {:#018x} RCX: {:#018x}", self.rax, self.rbx, self.rcx)?;
+        writeln!(f, "RDX: {:#018x} RSI: {:#018x} RDI: {:#018x}", self.rdx, self.rsi, self.rdi)?;
+        writeln!(f, "RBP: {:#018x} R8:  {:#018x} R9:  {:#018x}", self.rbp, self.r8, self.r9)?;
+        writeln!(f, "R10: {:#018x} R11: {:#018x} R12: {:#018x}", self.r10, self.r11, self.r12)?;
+        write!(f, "R13: {:#018x} R14: {:#018x} R15: {:#018x}", self.r13, self.r14, self.r15)
+    }
+}
+
+// NMI 的入口：保存所有通用寄存器，以 `Registers` 的形式交给 nmi_handler。
+// CPU 压入栈帧之前把栈按 16 字节对齐，压入 5 + 15 个值之后 call 时仍然对齐。
+global_asm!(
+    ".global nmi_entry",
+    "nmi_entry:",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov rdi, rsp",
+    "cld",
+    "call {handler}",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "iretq",
+    handler = sym nmi_handler,
+);
+
+extern "C" {
+    fn nmi_entry();
+}
+
+extern "C" fn nmi_handler(registers: &mut Registers) {
+    // 看门狗的 NMI 不再报告
+    if watchdog::handle_nmi(registers) {
+        stats::record(2);
+        return;
+    }
+    exceptions::notify_nmi(&mut registers.frame);
+}
+
+/// 在 IDT 中设置 NMI 的处理函数，使用独立的 IST 栈
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    unsafe {
+        idt.non_maskable_interrupt
+            .set_handler_addr(VirtAddr::from_ptr(nmi_entry as *const ()))
+            .set_stack_index(gdt::NMI_IST_INDEX);
+    }
+}
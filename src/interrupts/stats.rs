@@ -0,0 +1,104 @@
+use super::irq::{self, InterruptController, IRQ_COUNT};
+use super::{apic, exceptions, PIC_1_OFFSET};
+use crate::serial_println;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// IDT 中的中断向量数量
+pub const VECTOR_COUNT: usize = 256;
+
+// 每个中断向量被触发的次数
+static COUNTS: [AtomicU64; VECTOR_COUNT] = [const { AtomicU64::new(0) }; VECTOR_COUNT];
+
+/// 由每个中断和异常处理函数在入口处调用
+pub(crate) fn record(vector: u8) {
+    COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// `vector` 被触发的次数
+pub fn count(vector: u8) -> u64 {
+    COUNTS[usize::from(vector)].load(Ordering::Relaxed)
+}
+
+/// 所有向量的计数快照
+pub fn counts() -> [u64; VECTOR_COUNT] {
+    core::array::from_fn(|vector| COUNTS[vector].load(Ordering::Relaxed))
+}
+
+/// 所有中断和异常的总次数
+pub fn total() -> u64 {
+    COUNTS.iter().map(|count| count.load(Ordering::Relaxed)).sum()
+}
+
+/// 类似 `/proc/interrupts` 的中断统计表，格式化时读取当前的计数
+pub struct InterruptTable;
+
+// 传统 IRQ 线的设备名
+fn irq_name(irq: u8) -> &'static str {
+    match irq {
+        irq::TIMER => "timer",
+        irq::KEYBOARD => "keyboard",
+        _ => "",
+    }
+}
+
+impl fmt::Display for InterruptTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts = counts();
+        let chip = match irq::controller() {
+            InterruptController::Pic => "XT-PIC",
+            InterruptController::Apic => "IO-APIC",
+        };
+        writeln!(f, "{:>5} {:>10}", "", "CPU0")?;
+
+        // 已注册处理函数或者触发过的 IRQ 线，按 IRQ 号列出
+        for irq in 0..IRQ_COUNT as u8 {
+            let count = counts[usize::from(PIC_1_OFFSET + irq)];
+            if count != 0 || irq::handler_count(irq) != 0 {
+                writeln!(f, "{:>4}: {:>10}  {:<8} {}", irq, count, chip, irq_name(irq))?;
+            }
+        }
+
+        // 其余触发过的向量：异常用助记符，其他的用十六进制向量号
+        for (vector, &count) in counts.iter().enumerate() {
+            let vector = vector as u8;
+            let is_irq = (PIC_1_OFFSET..PIC_1_OFFSET + IRQ_COUNT as u8).contains(&vector);
+            if count == 0 || is_irq || vector == apic::SPURIOUS_VECTOR {
+                continue;
+            }
+            match exceptions::exception_name(vector) {
+                Some((mnemonic, name)) => writeln!(f, "{:>4}: {:>10}  {}", mnemonic, count, name)?,
+                None if vector == crate::time::apic_timer::VECTOR => {
+                    writeln!(f, "{:>4}: {:>10}  Local timer interrupts", "LOC", count)?
+                }
+                None => writeln!(f, "{:>4x}: {:>10}", vector, count)?,
+            }
+        }
+
+        // APIC 的伪中断向量和 8259 PIC 的伪 IRQ 7/15
+        let spurious = counts[usize::from(apic::SPURIOUS_VECTOR)] + irq::spurious_count();
+        write!(f, "{:>4}: {:>10}  Spurious interrupts", "SPU", spurious)
+    }
+}
+
+/// 通过串口输出中断统计表
+pub fn dump() {
+    serial_println!("{}", InterruptTable);
+}
+
+#[test_case]
+fn test_breakpoint_is_counted() {
+    let before = count(3);
+    x86_64::instructions::interrupts::int3();
+    assert_eq!(count(3), before + 1);
+}
+
+#[test_case]
+fn test_table_lists_timer() {
+    use alloc::string::ToString;
+
+    let table = InterruptTable.to_string();
+    assert!(table.lines().next().unwrap().ends_with("CPU0"));
+    assert!(table.lines().any(|line| line.trim_start().starts_with("0:") && line.ends_with("timer")));
+    assert!(table.lines().last().unwrap().trim_start().starts_with("SPU:"));
+}
@@ -2,21 +2,52 @@
 #![cfg_attr(test, no_main)]
 #![feature(custom_test_frameworks)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
+use x86_64::VirtAddr;
 
+pub mod acpi;
+pub mod allocator;
+pub mod breakpoint;
 pub mod serial;
 pub mod vga_buffer;
 pub mod interrupts;
 pub mod gdt;
+pub mod memory;
+pub mod time;
+pub mod watchdog;
 
-pub fn init() {
-    gdt::init(); // 初始化全局描述符表
+pub fn init(boot_info: &'static BootInfo) {
     interrupts::init_idt(); // 初始化中断描述符表
+    init_memory(boot_info); // 初始化内存管理和内核堆
+    gdt::init(); // 初始化全局描述符表，IST 栈需要从已初始化的内存中分配
     unsafe { interrupts::PICS.lock().initialize() }; // 初始化可编程中断控制器
+    interrupts::init_irqs(); // 注册定时器和键盘的处理函数，有 APIC 时改用 APIC
+    time::init_pit(time::DEFAULT_TICK_HZ); // 设置时钟中断的频率
     x86_64::instructions::interrupts::enable(); // 启用中断
+    time::calibrate(); // 以 PIT 为参考校准 TSC 和 APIC 定时器
+}
+
+/// 初始化页表映射器、物理帧分配器和内核堆
+pub fn init_memory(boot_info: &'static BootInfo) {
+    // 启用 NX 位和写保护，写时复制和 W^X 都依赖于它们
+    memory::protect::enable_enforcement();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) }; // 创建页表映射器
+    let mut frame_allocator = unsafe {
+        memory::buddy::BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset)
+    }; // 创建伙伴系统帧分配器
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed"); // 映射内核堆
+    memory::install(mapper, frame_allocator);
+    memory::protect::protect_kernel_image(); // 按段设置内核映像的页面权限
+    memory::stack::init(); // 登记启动栈的保护页
 }
 
 pub trait Testable {
@@ -55,6 +86,8 @@ pub fn test_runner(tests: &[&dyn Testable]) {
     for test in tests {
         test.run();
     }
+    #[cfg(feature = "heap-debug")]
+    allocator::debug::dump_live(); // 测试结束时输出仍然存活的分配
     exit_qemu(QemuExitCode::Success);
 }
 
@@ -72,11 +105,16 @@ pub fn hlt_loop() -> ! {
     }
 }
 
+#[cfg(test)]
+use bootloader::entry_point;
+
+#[cfg(test)]
+entry_point!(test_kernel_main);
+
 /// Entry point for `cargo test`
 #[cfg(test)]
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-    init();
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    init(boot_info);
     test_main();
     hlt_loop();
 }
@@ -86,3 +124,9 @@ pub extern "C" fn _start() -> ! {
 fn panic(info: &PanicInfo) -> ! {
     test_panic_handler(info)
 }
+
+// 内存分配失败时调用，通过 panic 报告失败的布局
+#[alloc_error_handler]
+fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
+    panic!("allocation error: {:?}", layout)
+}
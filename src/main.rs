@@ -4,20 +4,28 @@
 #![test_runner(blog_os::test_runner)] // 设置测试框架
 #![reexport_test_harness_main = "test_main"] // 测试框架入口函数
 
+extern crate alloc;
+
 use blog_os::println;
+use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 
-#[unsafe(no_mangle)] // 不重整函数名
-pub extern "C" fn _start() -> ! {
+// 使用 bootloader 提供的 entry_point 宏定义入口点，
+// 宏会生成真正的 `_start` 并对 kernel_main 的签名进行类型检查
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
     /*
-    _start 函数是程序的入口点，这个函数是一个裸函数，没有 Rust 的运行时支持。
+    kernel_main 是内核的入口点，bootloader 会把 BootInfo（内存映射等信息）传递进来。
      */
-    // this function is the entry point, since the linker looks for a function
-    // named `_start` by default
 
     println!("Hello World!");
 
-    blog_os::init(); // 初始化 IDT
+    blog_os::init(boot_info); // 初始化 IDT、内存管理和内核堆
+    println!(
+        "Interrupt controller: {:?}",
+        blog_os::interrupts::irq::controller()
+    );
 
     // invoke a breakpoint exception
     // x86_64::instructions::interrupts::int3();
@@ -50,14 +58,88 @@ pub extern "C" fn _start() -> ! {
     // unsafe { *ptr = 42; }
     // println!("write worked");
 
-    use x86_64::registers::control::Cr3;
+    use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+    use blog_os::memory;
+    use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags};
+    use x86_64::VirtAddr;
+
+    memory::inspect::dump(); // 通过串口输出当前的所有映射
+
+    memory::with_mapper(|mapper, frame_allocator| {
+        println!(
+            "Frames: total {}, used {}, free {}",
+            frame_allocator.total_frames(),
+            frame_allocator.used_frames(),
+            frame_allocator.free_frames()
+        );
+
+        // 地址转换测试
+        let addresses = [
+            0xb8000,                          // VGA 缓冲区，恒等映射
+            0x201008,                         // 某个代码页
+            0x0100_0020_1a10,                 // 某个栈页
+            boot_info.physical_memory_offset, // 映射到物理地址 0 的虚拟地址
+        ];
+        for &address in &addresses {
+            let virt = VirtAddr::new(address);
+            let phys = memory::translate_addr(mapper, virt);
+            println!("{:?} -> {:?}", virt, phys);
+        }
+
+        // 映射一个新页面，写入后再修改标志位并取消映射
+        let page = Page::containing_address(VirtAddr::new(0xdeadbeaf000));
+        let frame = frame_allocator.allocate_frame().expect("no more frames");
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { memory::map_page(mapper, page, frame, flags, frame_allocator) }
+            .expect("map_page failed");
+        let page_ptr: *mut u64 = page.start_address().as_mut_ptr();
+        unsafe { page_ptr.write_volatile(0x_f021_f077_f065_f04e) };
+        let phys = memory::translate_addr(mapper, page.start_address());
+        println!("{:?} -> {:?}", page.start_address(), phys);
+        unsafe { memory::update_flags(mapper, page, PageTableFlags::PRESENT) }
+            .expect("update_flags failed"); // 改为只读
+        memory::unmap_page(mapper, page).expect("unmap_page failed");
+        let phys = memory::translate_addr(mapper, page.start_address());
+        println!("{:?} -> {:?}", page.start_address(), phys);
+
+        println!(
+            "Frames: total {}, used {}, free {}",
+            frame_allocator.total_frames(),
+            frame_allocator.used_frames(),
+            frame_allocator.free_frames()
+        );
+        println!("Free blocks per order: {:?}", frame_allocator.stats().free_blocks);
+    });
+
+    // 在堆上分配一个数字
+    let heap_value = Box::new(41);
+    println!("heap_value at {:p}", heap_value);
 
-    let (level_4_page_table, _) = Cr3::read();
-    println!("Level 4 page table at: {:?}", level_4_page_table.start_address());
+    // 创建一个动态大小的 vector
+    let mut vec = Vec::new();
+    for i in 0..500 {
+        vec.push(i);
+    }
+    println!("vec at {:p}", vec.as_slice());
+
+    // 创建一个引用计数的 vector，计数归零时会被释放
+    let reference_counted = Rc::new(vec![1, 2, 3]);
+    let cloned_reference = reference_counted.clone();
+    println!("current reference count is {}", Rc::strong_count(&cloned_reference));
+    core::mem::drop(reference_counted);
+    println!("reference count is {} now", Rc::strong_count(&cloned_reference));
     
     #[cfg(test)]
     test_main(); // 测试框架入口函数
 
+    println!("Uptime: {:?}", blog_os::time::uptime());
+    println!(
+        "TSC: {} Hz, clock event device: {:?}",
+        blog_os::time::tsc::frequency(),
+        blog_os::time::clockevent::device().map(|device| device.name())
+    );
+    println!("Watchdog: {:?}", blog_os::watchdog::init()); // 时钟停止 10 秒后报告卡死
+    blog_os::interrupts::stats::dump(); // 通过串口输出中断统计
     println!("It did not crash!");
     blog_os::hlt_loop();
     // loop {
@@ -0,0 +1,625 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use buddy::BuddyFrameAllocator;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::{
+    FlagUpdateError, MapToError, MappedFrame, TranslateResult, UnmapError,
+};
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+pub mod buddy;
+pub mod cow;
+pub mod demand;
+pub mod extable;
+pub mod inspect;
+pub mod protect;
+pub mod stack;
+pub mod vmalloc;
+
+// -----------------
+// 页表映射
+// -----------------
+
+/// 初始化一个新的 OffsetPageTable
+///
+/// # Safety
+///
+/// 调用者必须保证完整的物理内存被映射到 `physical_memory_offset` 开始的虚拟地址处，
+/// 并且该函数只能被调用一次，以避免出现多个 `&mut` 引用指向同一个页表。
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let level_4_table = active_level_4_table(physical_memory_offset);
+    OffsetPageTable::new(level_4_table, physical_memory_offset)
+}
+
+/// 全局页表映射器，在 `blog_os::init` 中安装
+pub static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+
+/// 全局物理帧分配器，在 `blog_os::init` 中安装
+pub static FRAME_ALLOCATOR: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+
+// 物理内存映射的起始虚拟地址
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// 把映射器和帧分配器交给全局变量，供之后的内核代码使用
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BuddyFrameAllocator) {
+    PHYSICAL_MEMORY_OFFSET.store(mapper.phys_offset().as_u64(), Ordering::Relaxed);
+    *MAPPER.lock() = Some(mapper);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+}
+
+/// 在持有全局映射器和帧分配器的情况下执行闭包
+///
+/// 加锁顺序固定为先 MAPPER 后 FRAME_ALLOCATOR，避免死锁。持有锁时堆无法增长，
+/// 所以加锁之前先为闭包中的分配预留堆内存。
+pub fn with_mapper<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BuddyFrameAllocator) -> R,
+) -> R {
+    crate::allocator::reserve();
+    let mut mapper = MAPPER.lock();
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    f(
+        mapper.as_mut().expect("memory not initialized"),
+        frame_allocator.as_mut().expect("memory not initialized"),
+    )
+}
+
+/// 与 `with_mapper` 相同，但只尝试获取锁
+///
+/// 供异常处理函数使用：被中断的代码可能正持有这些锁，此时返回 `None` 而不是死锁。
+pub fn try_with_mapper<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BuddyFrameAllocator) -> R,
+) -> Option<R> {
+    let mut mapper = MAPPER.try_lock()?;
+    let mut frame_allocator = FRAME_ALLOCATOR.try_lock()?;
+    Some(f(mapper.as_mut()?, frame_allocator.as_mut()?))
+}
+
+/// 返回物理地址在物理内存映射中对应的虚拟地址
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) + addr.as_u64())
+}
+
+// 返回当前活动的 4 级页表的可变引用
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read(); // 读取 CR3 得到 4 级页表所在的物理帧
+
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64(); // 物理地址加偏移得到虚拟地址
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    &mut *page_table_ptr
+}
+
+/// 将虚拟地址转换为对应的物理地址，未映射时返回 `None`
+///
+/// 同样适用于 2 MiB 和 1 GiB 的大页映射。
+pub fn translate_addr(mapper: &impl Translate, addr: VirtAddr) -> Option<PhysAddr> {
+    mapper.translate_addr(addr)
+}
+
+/// 不获取任何锁，直接遍历当前的页表把虚拟地址转换为物理地址
+///
+/// 供 NMI 等不能等待锁、也不能触发异常的上下文使用。页表可能正被其他代码修改，
+/// 结果只是一个快照。
+pub fn translate_lockless(addr: VirtAddr) -> Option<PhysAddr> {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_frame, _) = Cr3::read();
+    let mut table = level_4_frame.start_address();
+    let indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    for (level, &index) in indexes.iter().enumerate() {
+        let entries = phys_to_virt(table).as_ptr::<PageTableEntry>();
+        let entry = unsafe { entries.add(usize::from(index)).read_volatile() };
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        // 3 级和 2 级页表中的大页表项直接指向 1 GiB 或 2 MiB 的物理帧
+        if (level == 1 || level == 2) && entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            let size: u64 = if level == 1 { 1 << 30 } else { 1 << 21 };
+            return Some(entry.addr() + (addr.as_u64() & (size - 1)));
+        }
+        table = entry.addr();
+    }
+    Some(table + u64::from(addr.page_offset()))
+}
+
+/// 将页面映射到给定的物理帧，并刷新 TLB
+///
+/// 页面大小可以是 4 KiB、2 MiB 或 1 GiB，大页映射会自动设置 `HUGE_PAGE` 标志。
+///
+/// # Safety
+///
+/// 调用者必须保证该帧没有被其他地方使用，否则会产生别名导致未定义行为。
+pub unsafe fn map_page<S: PageSize>(
+    mapper: &mut impl Mapper<S>,
+    page: Page<S>,
+    frame: PhysFrame<S>,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<S>> {
+    mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    Ok(())
+}
+
+/// 取消页面的映射并刷新 TLB，返回原先映射的物理帧
+pub fn unmap_page<S: PageSize>(
+    mapper: &mut impl Mapper<S>,
+    page: Page<S>,
+) -> Result<PhysFrame<S>, UnmapError> {
+    let (frame, flush) = mapper.unmap(page)?;
+    flush.flush();
+    Ok(frame)
+}
+
+/// 修改已映射页面的标志位并刷新 TLB
+///
+/// 页面必须以同样的大小映射，要修改大页中的一部分请使用 `split_and_update_flags`。
+///
+/// # Safety
+///
+/// 调用者必须保证新的标志位不会破坏内存安全，例如不能把仍在使用的页面设为不可写。
+pub unsafe fn update_flags<S: PageSize>(
+    mapper: &mut impl Mapper<S>,
+    page: Page<S>,
+    flags: PageTableFlags,
+) -> Result<(), FlagUpdateError> {
+    mapper.update_flags(page, flags)?.flush();
+    Ok(())
+}
+
+/// 修改一个 4 KiB 页面的标志位，如果它属于某个大页，先把大页拆分
+///
+/// # Safety
+///
+/// 与 `update_flags` 相同。
+pub unsafe fn split_and_update_flags(
+    mapper: &mut OffsetPageTable,
+    page: Page,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), FlagUpdateError> {
+    // 拆分失败时大页仍然存在，用 ParentEntryHugePage 报告
+    split_huge_page(mapper, page.start_address(), frame_allocator)
+        .map_err(|_| FlagUpdateError::ParentEntryHugePage)?;
+    update_flags(mapper, page, flags)
+}
+
+/// 把包含 `addr` 的大页拆分成 4 KiB 页面，映射的物理地址和标志位保持不变
+///
+/// 1 GiB 页面先拆成 2 MiB 页面，再把包含 `addr` 的那个拆成 4 KiB 页面。
+/// `addr` 未映射或已经是 4 KiB 页面时什么也不做。
+pub fn split_huge_page(
+    mapper: &mut OffsetPageTable,
+    addr: VirtAddr,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let offset = mapper.phys_offset();
+    let p4_entry = &mapper.level_4_table()[addr.p4_index()];
+    if p4_entry.is_unused() {
+        return Ok(());
+    }
+    let p3 = unsafe { next_table(offset, p4_entry) };
+    let p3_entry = &mut p3[addr.p3_index()];
+    if p3_entry.is_unused() {
+        return Ok(());
+    }
+    if p3_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        unsafe { split_entry(offset, p3_entry, Size2MiB::SIZE, frame_allocator)? };
+    }
+    let p2 = unsafe { next_table(offset, p3_entry) };
+    let p2_entry = &mut p2[addr.p2_index()];
+    if !p2_entry.is_unused() && p2_entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        unsafe { split_entry(offset, p2_entry, Size4KiB::SIZE, frame_allocator)? };
+    }
+    // invlpg 会使包含该地址的任意大小的 TLB 项失效
+    x86_64::instructions::tlb::flush(addr);
+    Ok(())
+}
+
+// 返回页表项指向的下一级页表
+unsafe fn next_table(offset: VirtAddr, entry: &PageTableEntry) -> &'static mut PageTable {
+    &mut *(offset + entry.addr().as_u64()).as_mut_ptr()
+}
+
+// 把一个大页表项替换为指向新页表的表项，新页表的 512 项覆盖原来的整个大页
+unsafe fn split_entry(
+    offset: VirtAddr,
+    entry: &mut PageTableEntry,
+    child_size: u64,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let table: &mut PageTable = &mut *(offset + frame.start_address().as_u64()).as_mut_ptr();
+
+    // 大页表项的第 12 位是 PAT，不属于物理地址
+    const HUGE_PAT: u64 = 1 << 12;
+    let pat = entry.addr().as_u64() & HUGE_PAT != 0;
+    let base = entry.addr().align_down(child_size * 512);
+    let leaf_flags = entry.flags();
+    let (child_flags, child_pat) = if child_size == Size4KiB::SIZE {
+        // 4 KiB 页表项中 PAT 位于第 7 位，即大页表项中 HUGE_PAGE 的位置
+        let flags = leaf_flags - PageTableFlags::HUGE_PAGE;
+        let flags = if pat { flags | PageTableFlags::HUGE_PAGE } else { flags };
+        (flags, 0)
+    } else {
+        (leaf_flags, if pat { HUGE_PAT } else { 0 })
+    };
+    for (index, child) in table.iter_mut().enumerate() {
+        child.set_addr(base + index as u64 * child_size + child_pat, child_flags);
+    }
+
+    // 上级表项不做限制，访问权限完全由新的叶子表项决定，
+    // 这样之后可以单独把其中一个页面设为可写
+    let mut parent_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    if leaf_flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+        parent_flags |= PageTableFlags::USER_ACCESSIBLE;
+    }
+    entry.set_addr(frame.start_address(), parent_flags);
+    Ok(())
+}
+
+// 2 MiB 大页对应的伙伴分配器阶数
+const HUGE_PAGE_ORDER: usize = 9;
+
+/// 为 `[start, start + size)` 分配物理帧并建立映射
+///
+/// 在地址对齐且剩余长度足够时使用 2 MiB 大页以节省 TLB 项，
+/// 伙伴分配器没有足够的连续内存时退回到 4 KiB 页面。
+/// 失败时已经建立的映射会被撤销。
+pub fn map_range(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+    frame_allocator: &mut BuddyFrameAllocator,
+) -> Result<(), MapToError<Size4KiB>> {
+    let end = start + size;
+    let mut addr = start;
+    while addr < end {
+        let result = if addr.is_aligned(Size2MiB::SIZE) && end - addr >= Size2MiB::SIZE {
+            map_huge_frame(mapper, addr, flags, frame_allocator)
+        } else {
+            Ok(None)
+        };
+        let result = match result {
+            Ok(Some(mapped)) => Ok(mapped),
+            Ok(None) => map_frame(mapper, addr, flags, frame_allocator),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(mapped) => addr += mapped,
+            Err(err) => {
+                unmap_range(mapper, start, addr - start, true, frame_allocator);
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+// 用一个新分配的 2 MiB 帧映射 addr，没有足够的连续内存时返回 Ok(None)
+fn map_huge_frame(
+    mapper: &mut OffsetPageTable,
+    addr: VirtAddr,
+    flags: PageTableFlags,
+    frame_allocator: &mut BuddyFrameAllocator,
+) -> Result<Option<u64>, MapToError<Size4KiB>> {
+    let frame = match frame_allocator.allocate_contiguous(HUGE_PAGE_ORDER) {
+        Some(frame) => PhysFrame::<Size2MiB>::containing_address(frame.start_address()),
+        None => return Ok(None),
+    };
+    let page = Page::<Size2MiB>::containing_address(addr);
+    match unsafe { map_page(mapper, page, frame, flags, frame_allocator) } {
+        Ok(()) => Ok(Some(Size2MiB::SIZE)),
+        Err(err) => {
+            let frame = PhysFrame::containing_address(frame.start_address());
+            unsafe { frame_allocator.deallocate(frame, HUGE_PAGE_ORDER) };
+            Err(match err {
+                MapToError::FrameAllocationFailed => MapToError::FrameAllocationFailed,
+                MapToError::ParentEntryHugePage => MapToError::ParentEntryHugePage,
+                MapToError::PageAlreadyMapped(frame) => MapToError::PageAlreadyMapped(
+                    PhysFrame::containing_address(frame.start_address()),
+                ),
+            })
+        }
+    }
+}
+
+// 用一个新分配的 4 KiB 帧映射 addr
+fn map_frame(
+    mapper: &mut OffsetPageTable,
+    addr: VirtAddr,
+    flags: PageTableFlags,
+    frame_allocator: &mut BuddyFrameAllocator,
+) -> Result<u64, MapToError<Size4KiB>> {
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let page = Page::<Size4KiB>::containing_address(addr);
+    if let Err(err) = unsafe { map_page(mapper, page, frame, flags, frame_allocator) } {
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        return Err(err);
+    }
+    Ok(Size4KiB::SIZE)
+}
+
+/// 取消 `[start, start + size)` 中所有映射，未映射的页面会被跳过
+///
+/// `free_frames` 为真时，把映射的帧归还给伙伴分配器（只能用于由它分配的帧）。
+/// 只有一部分落在范围内的大页会先被拆分，变空的 1 级页表会被释放。
+pub fn unmap_range(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    size: u64,
+    free_frames: bool,
+    frame_allocator: &mut BuddyFrameAllocator,
+) {
+    let end = start + size;
+    let mut addr = start;
+    while addr < end {
+        let frame = match mapper.translate(addr) {
+            TranslateResult::Mapped { frame, .. } => frame,
+            _ => {
+                addr += Size4KiB::SIZE;
+                continue;
+            }
+        };
+        let page_size = match frame {
+            MappedFrame::Size4KiB(_) => Size4KiB::SIZE,
+            MappedFrame::Size2MiB(_) => Size2MiB::SIZE,
+            MappedFrame::Size1GiB(_) => Size1GiB::SIZE,
+        };
+        if !addr.is_aligned(page_size) || end - addr < page_size {
+            // 大页只有一部分需要取消映射，拆分后重新处理当前地址
+            split_huge_page(mapper, addr, frame_allocator).expect("splitting huge page failed");
+            continue;
+        }
+        match frame {
+            MappedFrame::Size4KiB(_) => {
+                let frame = unmap_page(mapper, Page::<Size4KiB>::containing_address(addr))
+                    .expect("unmapping 4KiB page failed");
+                // 写时复制共享的帧只有在最后一个引用消失时才释放
+                if free_frames && cow::release_frame(frame) {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+            }
+            MappedFrame::Size2MiB(_) => {
+                let frame = unmap_page(mapper, Page::<Size2MiB>::containing_address(addr))
+                    .expect("unmapping 2MiB page failed");
+                if free_frames {
+                    let frame = PhysFrame::containing_address(frame.start_address());
+                    unsafe { frame_allocator.deallocate(frame, HUGE_PAGE_ORDER) };
+                }
+            }
+            MappedFrame::Size1GiB(_) => {
+                // 1 GiB 的帧不可能来自伙伴分配器，只取消映射
+                unmap_page(mapper, Page::<Size1GiB>::containing_address(addr))
+                    .expect("unmapping 1GiB page failed");
+            }
+        }
+        addr += page_size;
+    }
+    free_empty_tables(mapper, start, end, frame_allocator);
+}
+
+// 释放覆盖的 2 MiB 区域完全落在 [start, end) 中、并且已经没有任何映射的 1 级页表，
+// 这些页表由拆分大页或映射 4 KiB 页面时从伙伴分配器分配
+fn free_empty_tables(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    end: VirtAddr,
+    frame_allocator: &mut BuddyFrameAllocator,
+) {
+    let offset = mapper.phys_offset();
+    let mut addr = start.align_up(Size2MiB::SIZE);
+    while addr < end && end - addr >= Size2MiB::SIZE {
+        let p4_entry = &mapper.level_4_table()[addr.p4_index()];
+        if let Some(p2_entry) = unsafe { level_2_entry(offset, p4_entry, addr) } {
+            let p1 = unsafe { next_table(offset, p2_entry) };
+            if p1.iter().all(PageTableEntry::is_unused) {
+                let frame = PhysFrame::containing_address(p2_entry.addr());
+                p2_entry.set_unused();
+                // invlpg 同时会使分页结构缓存失效
+                x86_64::instructions::tlb::flush(addr);
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+        addr += Size2MiB::SIZE;
+    }
+}
+
+// 返回 `addr` 对应的、指向 1 级页表的 2 级页表项，不存在或是大页时返回 None
+unsafe fn level_2_entry(
+    offset: VirtAddr,
+    p4_entry: &PageTableEntry,
+    addr: VirtAddr,
+) -> Option<&'static mut PageTableEntry> {
+    let is_table = |entry: &PageTableEntry| {
+        !entry.is_unused() && !entry.flags().contains(PageTableFlags::HUGE_PAGE)
+    };
+    if p4_entry.is_unused() {
+        return None;
+    }
+    let p3_entry = &next_table(offset, p4_entry)[addr.p3_index()];
+    if !is_table(p3_entry) {
+        return None;
+    }
+    let p2_entry = &mut next_table(offset, p3_entry)[addr.p2_index()];
+    is_table(p2_entry).then_some(p2_entry)
+}
+
+// -----------------
+// 物理帧分配器
+// -----------------
+
+/// 从 bootloader 提供的内存映射中分配可用物理帧的帧分配器
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    region: usize,  // 当前分配所在的内存区域的下标
+    next_addr: u64, // 当前区域中下一个要分配的帧的地址
+    used: usize,    // 已经分配出去的帧数
+}
+
+impl BootInfoFrameAllocator {
+    /// 使用传入的内存映射创建帧分配器
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证传入的内存映射是有效的，
+    /// 即所有标记为 `Usable` 的帧确实没有被使用。
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        BootInfoFrameAllocator {
+            memory_map,
+            region: 0,
+            next_addr: 0,
+            used: 0,
+        }
+    }
+
+    // 返回内存映射中所有可用帧的迭代器
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        // 只保留标记为 Usable 的区域
+        let regions = self.memory_map.iter();
+        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
+        // 将每个区域映射为其地址范围
+        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
+        // 以 4KiB 为步长得到每个帧的起始地址
+        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
+        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+
+    /// 可用帧的总数
+    pub fn total_frames(&self) -> usize {
+        self.usable_frames().count()
+    }
+
+    /// 已经分配出去的帧数
+    pub fn used_frames(&self) -> usize {
+        self.used
+    }
+
+    /// 剩余可分配的帧数
+    pub fn free_frames(&self) -> usize {
+        self.total_frames() - self.used_frames()
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        // 从上次停下的位置继续，不必每次都从头遍历内存映射
+        while let Some(region) = self.memory_map.get(self.region) {
+            if region.region_type == MemoryRegionType::Usable {
+                let addr = self.next_addr.max(region.range.start_addr());
+                if addr < region.range.end_addr() {
+                    self.next_addr = addr + 4096;
+                    self.used += 1;
+                    return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+                }
+            }
+            self.region += 1;
+            self.next_addr = 0;
+        }
+        None
+    }
+}
+
+#[test_case]
+fn test_translate_physical_memory_mapping() {
+    // bootloader 用大页映射整个物理内存，转换必须穿过大页
+    let phys = PhysAddr::new(0x12_3456);
+    let virt = phys_to_virt(phys);
+    with_mapper(|mapper, _| assert_eq!(translate_addr(mapper, virt), Some(phys)));
+}
+
+#[test_case]
+fn test_translate_lockless_matches_mapper() {
+    let stack_value = 0u64;
+    let stack = VirtAddr::from_ptr(&stack_value);
+    let huge = phys_to_virt(PhysAddr::new(0x12_3456));
+    for addr in [stack, huge] {
+        let expected = with_mapper(|mapper, _| translate_addr(mapper, addr));
+        assert_eq!(translate_lockless(addr), expected);
+    }
+    assert_eq!(translate_lockless(VirtAddr::new(0x_5555_0000_0000)), None);
+}
+
+#[test_case]
+fn test_split_huge_page_for_flag_update() {
+    use inspect::flags_of;
+
+    let addr = vmalloc::vmalloc(Size2MiB::SIZE as usize).expect("vmalloc failed");
+    assert!(flags_of(addr).unwrap().contains(PageTableFlags::HUGE_PAGE));
+    let phys_before = with_mapper(|mapper, _| translate_addr(mapper, addr + 0x5000u64));
+
+    // 只把大页中的第 5 页改为只读
+    let page = Page::containing_address(addr + 0x5000u64);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    with_mapper(|mapper, frame_allocator| unsafe {
+        split_and_update_flags(mapper, page, flags, frame_allocator)
+    })
+    .expect("split_and_update_flags failed");
+
+    let read_only = flags_of(page.start_address()).unwrap();
+    assert!(!read_only.contains(PageTableFlags::WRITABLE));
+    assert!(!read_only.contains(PageTableFlags::HUGE_PAGE));
+    assert!(flags_of(addr).unwrap().contains(PageTableFlags::WRITABLE));
+    // 拆分后物理地址保持不变
+    let phys_after = with_mapper(|mapper, _| translate_addr(mapper, addr + 0x5000u64));
+    assert_eq!(phys_before, phys_after);
+
+    vmalloc::vfree(addr);
+}
+
+#[test_case]
+fn test_split_read_only_huge_page_allows_writable_page() {
+    use inspect::flags_of;
+
+    let addr = vmalloc::vmalloc(Size2MiB::SIZE as usize).expect("vmalloc failed");
+    let read_only = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    with_mapper(|mapper, _| unsafe {
+        update_flags(mapper, Page::<Size2MiB>::containing_address(addr), read_only)
+    })
+    .expect("update_flags failed");
+
+    // 拆分后上级表项不再限制写入，单独的 4 KiB 页面可以改为可写
+    let page = Page::containing_address(addr + 0x3000u64);
+    with_mapper(|mapper, frame_allocator| unsafe {
+        split_and_update_flags(mapper, page, read_only | PageTableFlags::WRITABLE, frame_allocator)
+    })
+    .expect("split_and_update_flags failed");
+    assert!(flags_of(page.start_address()).unwrap().contains(PageTableFlags::WRITABLE));
+    assert!(!flags_of(addr).unwrap().contains(PageTableFlags::WRITABLE));
+
+    vmalloc::vfree(addr);
+}
+
+#[test_case]
+fn test_unmap_range_frees_split_tables() {
+    let addr = vmalloc::vmalloc(Size2MiB::SIZE as usize).expect("vmalloc failed");
+    let has_level_1_table = |mapper: &mut OffsetPageTable| {
+        let offset = mapper.phys_offset();
+        let p4_entry = &mapper.level_4_table()[addr.p4_index()];
+        unsafe { level_2_entry(offset, p4_entry, addr) }.is_some()
+    };
+    with_mapper(|mapper, frame_allocator| {
+        split_huge_page(mapper, addr, frame_allocator).expect("split_huge_page failed");
+        assert!(has_level_1_table(mapper));
+    });
+    // 取消整个 2 MiB 区域的映射后，拆分时分配的 1 级页表被释放
+    vmalloc::vfree(addr);
+    with_mapper(|mapper, _| assert!(!has_level_1_table(mapper)));
+}
@@ -0,0 +1,250 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// 最大的阶，最大的块为 2^MAX_ORDER 个帧（4 MiB）
+pub const MAX_ORDER: usize = 10;
+
+const FRAME_SIZE: u64 = 4096;
+
+// 空闲链表的结束标记，物理地址不会取到这个值
+const NIL: u64 = u64::MAX;
+
+/// 物理内存区域，用于满足只能访问低地址的旧设备
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// 低于 16 MiB，ISA DMA 设备只能访问这部分内存
+    Dma,
+    /// 低于 4 GiB，只支持 32 位地址的设备使用
+    Dma32,
+    /// 任意物理内存
+    Normal,
+}
+
+impl Zone {
+    // 该区域的结束地址（不包含）
+    fn limit(self) -> u64 {
+        match self {
+            Zone::Dma => 16 << 20,
+            Zone::Dma32 => 4 << 30,
+            Zone::Normal => u64::MAX,
+        }
+    }
+}
+
+/// 每个阶的空闲块统计
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuddyStats {
+    pub free_blocks: [usize; MAX_ORDER + 1], // 下标为阶
+}
+
+/// 伙伴系统物理帧分配器
+///
+/// 每个阶维护一个空闲块链表，链表节点直接存放在空闲帧中，
+/// 通过 bootloader 建立的物理内存映射访问。
+/// 阶为 `n` 的块包含 2^n 个连续的帧，并且按自身大小对齐。
+pub struct BuddyFrameAllocator {
+    physical_memory_offset: VirtAddr,
+    free_lists: [u64; MAX_ORDER + 1], // 每个阶空闲链表的头节点物理地址
+    free_counts: [usize; MAX_ORDER + 1],
+    total_frames: usize,
+}
+
+impl BuddyFrameAllocator {
+    /// 使用 bootloader 的内存映射创建伙伴分配器
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证内存映射中标记为 `Usable` 的帧确实没有被使用，
+    /// 并且完整的物理内存被映射到 `physical_memory_offset` 处。
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let mut allocator = BuddyFrameAllocator {
+            physical_memory_offset,
+            free_lists: [NIL; MAX_ORDER + 1],
+            free_counts: [0; MAX_ORDER + 1],
+            total_frames: 0,
+        };
+        let usable_regions = memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable);
+        for region in usable_regions {
+            allocator.add_region(region.range.start_addr(), region.range.end_addr());
+        }
+        allocator
+    }
+
+    // 把一段空闲的物理内存拆分成尽可能大的对齐块加入空闲链表
+    unsafe fn add_region(&mut self, start: u64, end: u64) {
+        let mut addr = start;
+        while addr + FRAME_SIZE <= end {
+            let mut order = MAX_ORDER;
+            while !addr.is_multiple_of(block_size(order)) || addr + block_size(order) > end {
+                order -= 1;
+            }
+            self.push_free(addr, order);
+            self.total_frames += 1 << order;
+            addr += block_size(order);
+        }
+    }
+
+    // 返回物理地址对应的链表节点指针
+    fn node(&self, addr: u64) -> *mut u64 {
+        (self.physical_memory_offset + addr).as_mut_ptr()
+    }
+
+    unsafe fn push_free(&mut self, addr: u64, order: usize) {
+        self.node(addr).write(self.free_lists[order]);
+        self.free_lists[order] = addr;
+        self.free_counts[order] += 1;
+    }
+
+    // 从阶为 order 的空闲链表中移除第一个满足条件的块
+    unsafe fn take_free(&mut self, order: usize, accept: impl Fn(u64) -> bool) -> Option<u64> {
+        let mut prev: Option<u64> = None;
+        let mut current = self.free_lists[order];
+        while current != NIL {
+            let next = self.node(current).read();
+            if accept(current) {
+                match prev {
+                    Some(prev) => self.node(prev).write(next),
+                    None => self.free_lists[order] = next,
+                }
+                self.free_counts[order] -= 1;
+                return Some(current);
+            }
+            prev = Some(current);
+            current = next;
+        }
+        None
+    }
+
+    /// 分配 2^order 个物理上连续的帧
+    pub fn allocate_contiguous(&mut self, order: usize) -> Option<PhysFrame> {
+        self.allocate_aligned(order, 0, Zone::Normal)
+    }
+
+    /// 在给定区域中分配 2^order 个物理上连续的帧
+    pub fn allocate_contiguous_in(&mut self, order: usize, zone: Zone) -> Option<PhysFrame> {
+        self.allocate_aligned(order, 0, zone)
+    }
+
+    /// 分配 2^order 个连续的帧，起始地址按 `align` 字节对齐并位于 `zone` 中
+    ///
+    /// `align` 必须是 2 的幂或 0；块本身已按其大小对齐，
+    /// 只有 `align` 大于块大小时才会产生额外的限制。
+    pub fn allocate_aligned(&mut self, order: usize, align: u64, zone: Zone) -> Option<PhysFrame> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        let size = block_size(order);
+        let limit = zone.limit();
+        let accept = |addr: u64| addr.is_multiple_of(align.max(1)) && addr + size <= limit;
+
+        for current_order in order..=MAX_ORDER {
+            // 拆分得到的块总是位于大块的起始处，所以可以直接检查大块的地址
+            if let Some(addr) = unsafe { self.take_free(current_order, accept) } {
+                // 把多余的部分逐阶放回空闲链表
+                for split_order in (order..current_order).rev() {
+                    unsafe { self.push_free(addr + block_size(split_order), split_order) };
+                }
+                return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+            }
+        }
+        None
+    }
+
+    /// 释放由 `allocate_*` 分配的 2^order 个连续帧
+    ///
+    /// # Safety
+    ///
+    /// 调用者必须保证这些帧由本分配器以相同的阶分配，并且已不再被使用。
+    pub unsafe fn deallocate(&mut self, frame: PhysFrame, order: usize) {
+        let mut addr = frame.start_address().as_u64();
+        let mut order = order;
+        // 伙伴也空闲时合并成更高阶的块
+        while order < MAX_ORDER {
+            let buddy = addr ^ block_size(order);
+            if self.take_free(order, |a| a == buddy).is_none() {
+                break;
+            }
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        self.push_free(addr, order);
+    }
+
+    /// 每个阶的空闲块数量
+    pub fn stats(&self) -> BuddyStats {
+        BuddyStats {
+            free_blocks: self.free_counts,
+        }
+    }
+
+    /// 可用帧的总数
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// 已经分配出去的帧数
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames()
+    }
+
+    /// 剩余可分配的帧数
+    pub fn free_frames(&self) -> usize {
+        self.free_counts
+            .iter()
+            .enumerate()
+            .map(|(order, count)| count << order)
+            .sum()
+    }
+}
+
+// 阶为 order 的块的字节数
+fn block_size(order: usize) -> u64 {
+    FRAME_SIZE << order
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate_contiguous(0)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate(frame, 0)
+    }
+}
+
+#[test_case]
+fn test_contiguous_allocation_is_aligned() {
+    let mut guard = super::FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let before = allocator.stats();
+
+    let frame = allocator.allocate_contiguous(3).expect("out of memory");
+    assert_eq!(frame.start_address().as_u64() % block_size(3), 0);
+    unsafe { allocator.deallocate(frame, 3) };
+
+    // 释放后伙伴重新合并，各阶的空闲块数量应当恢复
+    assert_eq!(allocator.stats(), before);
+}
+
+#[test_case]
+fn test_zone_and_alignment_constraints() {
+    let mut guard = super::FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+
+    let frame = allocator
+        .allocate_contiguous_in(1, Zone::Dma)
+        .expect("no memory below 16 MiB");
+    assert!(frame.start_address().as_u64() + block_size(1) <= Zone::Dma.limit());
+    unsafe { allocator.deallocate(frame, 1) };
+
+    let frame = allocator
+        .allocate_aligned(0, 64 * 1024, Zone::Dma32)
+        .expect("no aligned memory below 4 GiB");
+    assert_eq!(frame.start_address().as_u64() % (64 * 1024), 0);
+    unsafe { allocator.deallocate(frame, 0) };
+}
@@ -0,0 +1,157 @@
+use super::{map_page, phys_to_virt, split_huge_page, try_with_mapper, unmap_page, update_flags, with_mapper};
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, OffsetPageTable, Page, PageTableFlags, PhysFrame, Translate,
+};
+use x86_64::VirtAddr;
+
+/// 标记写时复制页面的标志位（页表项中留给操作系统使用的第 9 位）
+pub const COW: PageTableFlags = PageTableFlags::BIT_9;
+
+// 被多个页面共享的帧的引用计数，只被一个页面映射的帧不在表中
+static FRAME_REFS: Mutex<BTreeMap<u64, usize>> = Mutex::new(BTreeMap::new());
+
+/// 建立写时复制映射时可能出现的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CowError {
+    /// 源地址没有映射
+    NotMapped(VirtAddr),
+    /// 没有足够的物理帧用于页表
+    OutOfMemory,
+}
+
+/// 让 `dst` 开始的 `pages` 个页面以写时复制的方式共享 `src` 的物理帧
+///
+/// 两边的可写页面都会变成只读并带上 `COW` 标志，之后任意一边写入时，
+/// 缺页处理函数会复制出一个私有的帧。`dst` 中原有的映射会被替换，其帧被释放。
+pub fn share(src: VirtAddr, dst: VirtAddr, pages: u64) -> Result<(), CowError> {
+    with_mapper(|mapper, frame_allocator| {
+        let mut refs = FRAME_REFS.lock();
+        for index in 0..pages {
+            let src_page = Page::containing_address(src + index * 4096);
+            let dst_page = Page::containing_address(dst + index * 4096);
+            // 写时复制以 4 KiB 页面为单位
+            split_huge_page(mapper, src_page.start_address(), frame_allocator)
+                .map_err(|_| CowError::OutOfMemory)?;
+            split_huge_page(mapper, dst_page.start_address(), frame_allocator)
+                .map_err(|_| CowError::OutOfMemory)?;
+
+            let (frame, flags) = translate_4kib(mapper, src_page)
+                .ok_or(CowError::NotMapped(src_page.start_address()))?;
+            if let Ok(old) = unmap_page(mapper, dst_page) {
+                if put_frame(&mut refs, old) {
+                    unsafe { frame_allocator.deallocate_frame(old) };
+                }
+            }
+
+            let shared = if flags.contains(PageTableFlags::WRITABLE) {
+                (flags - PageTableFlags::WRITABLE) | COW
+            } else {
+                flags // 只读页面直接共享
+            };
+            unsafe {
+                update_flags(mapper, src_page, shared).map_err(|_| CowError::OutOfMemory)?;
+                map_page(mapper, dst_page, frame, shared, frame_allocator)
+                    .map_err(|_| CowError::OutOfMemory)?;
+            }
+            *refs.entry(frame.start_address().as_u64()).or_insert(1) += 1;
+        }
+        Ok(())
+    })
+}
+
+/// 共享该帧的页面数量，未共享的帧返回 1
+pub fn reference_count(frame: PhysFrame) -> usize {
+    FRAME_REFS
+        .lock()
+        .get(&frame.start_address().as_u64())
+        .copied()
+        .unwrap_or(1)
+}
+
+// 取消一个页面对帧的引用，没有其他页面引用该帧时返回 true，调用者应释放它
+fn put_frame(refs: &mut BTreeMap<u64, usize>, frame: PhysFrame) -> bool {
+    let key = frame.start_address().as_u64();
+    match refs.get_mut(&key) {
+        Some(count) => {
+            *count -= 1;
+            if *count == 1 {
+                refs.remove(&key); // 剩下的页面独占该帧
+            }
+            false
+        }
+        None => true,
+    }
+}
+
+/// 页面被取消映射时调用，返回该帧是否已没有其他引用、可以释放
+pub(super) fn release_frame(frame: PhysFrame) -> bool {
+    put_frame(&mut FRAME_REFS.lock(), frame)
+}
+
+// 返回 4 KiB 页面映射的帧和页表项标志位
+fn translate_4kib(mapper: &OffsetPageTable, page: Page) -> Option<(PhysFrame, PageTableFlags)> {
+    match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } => Some((frame, flags)),
+        _ => None,
+    }
+}
+
+/// 尝试处理写时复制页面上的写保护异常，处理成功时返回 `true`
+///
+/// 帧仍被其他页面共享时复制出私有的帧；已经是最后一个引用时直接恢复可写。
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    let write_violation =
+        PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if !error_code.contains(write_violation) {
+        return false;
+    }
+    let page = Page::containing_address(addr);
+
+    try_with_mapper(|mapper, frame_allocator| {
+        let (frame, flags) = match translate_4kib(mapper, page) {
+            Some((frame, flags)) if flags.contains(COW) => (frame, flags),
+            _ => return false,
+        };
+        let mut refs = match FRAME_REFS.try_lock() {
+            Some(refs) => refs,
+            None => return false,
+        };
+        let writable = (flags - COW) | PageTableFlags::WRITABLE;
+
+        if !refs.contains_key(&frame.start_address().as_u64()) {
+            // 最后一个引用，不需要复制
+            return unsafe { update_flags(mapper, page, writable) }.is_ok();
+        }
+
+        let copy = match frame_allocator.allocate_frame() {
+            Some(copy) => copy,
+            None => return false,
+        };
+        unsafe {
+            let src: *const u8 = phys_to_virt(frame.start_address()).as_ptr();
+            let dst: *mut u8 = phys_to_virt(copy.start_address()).as_mut_ptr();
+            core::ptr::copy_nonoverlapping(src, dst, 4096);
+        }
+        if unmap_page(mapper, page).is_err() {
+            unsafe { frame_allocator.deallocate_frame(copy) };
+            return false;
+        }
+        if unsafe { map_page(mapper, page, copy, writable, frame_allocator) }.is_err() {
+            // 恢复原来的映射
+            let _ = unsafe { map_page(mapper, page, frame, flags, frame_allocator) };
+            unsafe { frame_allocator.deallocate_frame(copy) };
+            return false;
+        }
+        put_frame(&mut refs, frame);
+        true
+    })
+    .unwrap_or(false)
+}
@@ -0,0 +1,116 @@
+use super::{map_page, phys_to_virt, try_with_mapper};
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+/// 按需分配物理帧的虚拟地址区域
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LazyRegion {
+    pub start: VirtAddr,
+    pub end: VirtAddr, // 不包含
+    pub flags: PageTableFlags,
+}
+
+impl LazyRegion {
+    fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+static LAZY_REGIONS: Mutex<Vec<LazyRegion>> = Mutex::new(Vec::new());
+
+/// 登记一段按需分配的区域，首次访问其中的页面时才会映射一个清零的帧
+///
+/// 区域必须按页对齐，并且不能与已有的映射重叠。
+pub fn register_lazy_region(start: VirtAddr, size: u64, flags: PageTableFlags) {
+    assert!(start.is_aligned(4096u64) && size.is_multiple_of(4096), "lazy region must be page aligned");
+    let region = LazyRegion {
+        start,
+        end: start + size,
+        flags: flags | PageTableFlags::PRESENT,
+    };
+    let mut regions = LAZY_REGIONS.lock();
+    assert!(
+        !regions
+            .iter()
+            .any(|r| r.start < region.end && region.start < r.end),
+        "lazy region {:?}..{:?} overlaps an existing region",
+        region.start,
+        region.end
+    );
+    regions.push(region);
+}
+
+/// 取消登记起始于 `start` 的区域，已经映射的页面不受影响
+pub fn unregister_lazy_region(start: VirtAddr) -> Option<LazyRegion> {
+    let mut regions = LAZY_REGIONS.lock();
+    let index = regions.iter().position(|r| r.start == start)?;
+    Some(regions.remove(index))
+}
+
+/// 返回包含 `addr` 的按需分配区域
+pub fn find_lazy_region(addr: VirtAddr) -> Option<LazyRegion> {
+    LAZY_REGIONS.lock().iter().find(|r| r.contains(addr)).copied()
+}
+
+/// 尝试处理一次缺页，成功映射了页面时返回 `true`
+///
+/// 只处理按需分配区域中的“页面不存在”异常；保护违例、区域外的地址，
+/// 以及被中断的代码正持有内存管理的锁时都返回 `false`，由调用者按致命错误处理。
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+    let region = match LAZY_REGIONS.try_lock() {
+        Some(regions) => match regions.iter().find(|r| r.contains(addr)) {
+            Some(region) => *region,
+            None => return false,
+        },
+        None => return false,
+    };
+
+    let page = Page::containing_address(addr);
+    try_with_mapper(|mapper, frame_allocator| {
+        let frame = match frame_allocator.allocate_frame() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        // 先通过物理内存映射清零，再映射给发生缺页的地址
+        unsafe {
+            let ptr: *mut u8 = phys_to_virt(frame.start_address()).as_mut_ptr();
+            core::ptr::write_bytes(ptr, 0, 4096);
+        }
+        match unsafe { map_page(mapper, page, frame, region.flags, frame_allocator) } {
+            Ok(()) => true,
+            Err(_) => {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                false
+            }
+        }
+    })
+    .unwrap_or(false)
+}
+
+#[test_case]
+fn test_lazy_region_is_backed_on_access() {
+    use super::inspect::flags_of;
+    use super::vmalloc;
+
+    let addr = vmalloc::vmalloc_lazy(3 * 4096).expect("vmalloc_lazy failed");
+    assert!(flags_of(addr).is_none());
+    assert!(flags_of(addr + 4096u64).is_none());
+
+    let ptr: *mut u64 = addr.as_mut_ptr();
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0); // 新映射的帧已清零
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+    assert!(flags_of(addr).is_some());
+    assert!(flags_of(addr + 4096u64).is_none()); // 只映射被访问的页面
+
+    vmalloc::vfree(addr);
+    assert!(find_lazy_region(addr).is_none());
+}
@@ -0,0 +1,169 @@
+use core::arch::asm;
+use core::mem::{size_of, MaybeUninit};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+/// 异常表中的一项：`insn` 处的指令出错时，从 `fixup` 处继续执行
+///
+/// 表项由访问函数的内联汇编放到 `__ex_table` 段中，见 linker.ld。
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionTableEntry {
+    pub insn: u64,
+    pub fixup: u64,
+}
+
+// 由 linker.ld 导出的异常表边界
+extern "C" {
+    static __ex_table_start: ExceptionTableEntry;
+    static __ex_table_end: ExceptionTableEntry;
+}
+
+/// 受保护的访问触发的异常
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// 缺页，`addr` 为出错的地址
+    PageFault {
+        addr: VirtAddr,
+        error_code: PageFaultErrorCode,
+    },
+    /// 一般保护错误，例如访问非规范地址
+    GeneralProtection { error_code: u64 },
+}
+
+// 最近一次被修复的异常，访问函数在关中断时读取
+static LAST_FAULT: Mutex<Option<Fault>> = Mutex::new(None);
+
+/// 内核映像中的所有异常表项
+pub fn entries() -> &'static [ExceptionTableEntry] {
+    let start = &raw const __ex_table_start;
+    let end = &raw const __ex_table_end;
+    unsafe { core::slice::from_raw_parts(start, end.offset_from(start) as usize) }
+}
+
+/// 查找 `instruction` 处的指令出错时应当跳转到的修复地址
+pub fn search(instruction: VirtAddr) -> Option<VirtAddr> {
+    entries()
+        .iter()
+        .find(|entry| entry.insn == instruction.as_u64())
+        .map(|entry| VirtAddr::new(entry.fixup))
+}
+
+/// 由 #PF 和 #GP 的处理函数调用：出错的指令在异常表中时记录异常，
+/// 把返回地址改为修复代码并返回 true
+pub(crate) fn fixup(stack_frame: &mut InterruptStackFrame, fault: Fault) -> bool {
+    let Some(target) = search(stack_frame.instruction_pointer) else {
+        return false;
+    };
+    *LAST_FAULT.lock() = Some(fault);
+    unsafe {
+        stack_frame
+            .as_mut()
+            .update(|frame| frame.instruction_pointer = target);
+    }
+    true
+}
+
+/// 从 `src` 复制 `dst.len()` 个字节，地址无法访问时返回异常而不是崩溃
+///
+/// # Safety
+///
+/// `src` 处如果是可以访问的内存，读取它不能有副作用（例如 MMIO 寄存器）。
+/// 出错时 `dst` 中可能只复制了一部分。
+pub unsafe fn copy_from_unchecked(dst: &mut [u8], src: VirtAddr) -> Result<(), Fault> {
+    without_interrupts(|| {
+        let failed: u32;
+        // rep movsb 出错时跳到 4，设置 failed 后结束
+        asm!(
+            "xor {failed:e}, {failed:e}",
+            "2:",
+            "rep movsb",
+            "jmp 3f",
+            "4:",
+            "mov {failed:e}, 1",
+            "3:",
+            ".pushsection __ex_table, \"a\"",
+            ".balign 8",
+            ".quad 2b, 4b",
+            ".popsection",
+            failed = out(reg) failed,
+            inout("rdi") dst.as_mut_ptr() => _,
+            inout("rsi") src.as_u64() => _,
+            inout("rcx") dst.len() => _,
+            options(nostack),
+        );
+        match failed {
+            0 => Ok(()),
+            _ => Err(LAST_FAULT.lock().take().expect("fixup without a recorded fault")),
+        }
+    })
+}
+
+/// 从 `addr` 读取一个 `T`，地址无法访问时返回异常而不是崩溃
+///
+/// # Safety
+///
+/// 与 `copy_from_unchecked` 相同，并且 `addr` 处的字节必须是 `T` 的合法值。
+pub unsafe fn probe_read<T: Copy>(addr: VirtAddr) -> Result<T, Fault> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes = core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>());
+    copy_from_unchecked(bytes, addr)?;
+    Ok(value.assume_init())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 测试环境中没有映射的地址
+    const UNMAPPED: u64 = 0x_5555_0000_0000;
+
+    #[test_case]
+    fn test_probe_read_mapped() {
+        let value: u64 = 0x1234_5678_9abc_def0;
+        let read = unsafe { probe_read::<u64>(VirtAddr::from_ptr(&value)) };
+        assert_eq!(read, Ok(value));
+    }
+
+    #[test_case]
+    fn test_probe_read_unmapped_page_fault() {
+        let addr = VirtAddr::new(UNMAPPED);
+        match unsafe { probe_read::<u32>(addr) } {
+            Err(Fault::PageFault { addr: fault, error_code }) => {
+                assert_eq!(fault, addr);
+                assert!(!error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test_case]
+    fn test_probe_read_non_canonical_general_protection() {
+        let addr = unsafe { VirtAddr::new_unsafe(0x_8000_0000_0000) };
+        let result = unsafe { probe_read::<u8>(addr) };
+        assert!(matches!(result, Err(Fault::GeneralProtection { .. })), "{:?}", result);
+    }
+
+    #[test_case]
+    fn test_copy_from_unchecked() {
+        let source = *b"exception table!";
+        let mut buffer = [0u8; 16];
+        let result = unsafe { copy_from_unchecked(&mut buffer, VirtAddr::from_ptr(&source)) };
+        assert_eq!(result, Ok(()));
+        assert_eq!(buffer, source);
+
+        let result = unsafe { copy_from_unchecked(&mut buffer, VirtAddr::new(UNMAPPED)) };
+        assert!(matches!(result, Err(Fault::PageFault { .. })));
+    }
+
+    #[test_case]
+    fn test_search() {
+        assert!(!entries().is_empty());
+        assert_eq!(search(VirtAddr::new(0)), None);
+        for entry in entries() {
+            assert_eq!(search(VirtAddr::new(entry.insn)), Some(VirtAddr::new(entry.fixup)));
+        }
+    }
+}
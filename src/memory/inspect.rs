@@ -0,0 +1,217 @@
+use super::phys_to_virt;
+use crate::serial_println;
+use core::fmt;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::{PhysAddr, VirtAddr};
+
+// 输出中关心的标志位，其余位在比较和合并时被忽略
+const REPORTED_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE)
+    .union(PageTableFlags::NO_EXECUTE)
+    .union(PageTableFlags::HUGE_PAGE)
+    .union(PageTableFlags::GLOBAL);
+
+/// 一段连续的映射：虚拟地址和物理地址都连续，且有效标志位相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub virt_start: VirtAddr,
+    pub phys_start: PhysAddr,
+    pub size: u64,
+    /// 有效标志位：只有每一级都可写（用户可访问）时才算可写（用户可访问），
+    /// 任意一级设置了 NX 就不可执行
+    pub flags: PageTableFlags,
+}
+
+impl Mapping {
+    /// 映射的结束虚拟地址（不包含）
+    pub fn virt_end(&self) -> VirtAddr {
+        self.virt_start + self.size
+    }
+
+    // 判断 next 是否紧接在本映射之后，可以合并
+    fn can_merge(&self, next: &Mapping) -> bool {
+        self.virt_end() == next.virt_start
+            && self.phys_start + self.size == next.phys_start
+            && self.flags == next.flags
+    }
+}
+
+/// 以紧凑的单行格式输出，便于主机上的脚本解析：
+/// `<虚拟起始> <虚拟结束> <物理起始> <标志>`，标志依次为 P W U N H G，未设置时为 `-`。
+/// H 只出现在 2 MiB 和 1 GiB 的大页映射上。
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |flag: PageTableFlags, c: char| if self.flags.contains(flag) { c } else { '-' };
+        write!(
+            f,
+            "{:#018x} {:#018x} {:#018x} {}{}{}{}{}{}",
+            self.virt_start.as_u64(),
+            self.virt_end().as_u64(),
+            self.phys_start.as_u64(),
+            flag(PageTableFlags::PRESENT, 'P'),
+            flag(PageTableFlags::WRITABLE, 'W'),
+            flag(PageTableFlags::USER_ACCESSIBLE, 'U'),
+            flag(PageTableFlags::NO_EXECUTE, 'N'),
+            flag(PageTableFlags::HUGE_PAGE, 'H'),
+            flag(PageTableFlags::GLOBAL, 'G'),
+        )
+    }
+}
+
+// 返回物理地址处页表的引用
+unsafe fn table_at(addr: PhysAddr) -> &'static PageTable {
+    &*phys_to_virt(addr).as_ptr()
+}
+
+// 合并上一级和本级的标志位
+fn combine(parent: PageTableFlags, entry: PageTableFlags) -> PageTableFlags {
+    let mut flags = entry & REPORTED_FLAGS;
+    if !parent.contains(PageTableFlags::WRITABLE) {
+        flags.remove(PageTableFlags::WRITABLE);
+    }
+    if !parent.contains(PageTableFlags::USER_ACCESSIBLE) {
+        flags.remove(PageTableFlags::USER_ACCESSIBLE);
+    }
+    if parent.contains(PageTableFlags::NO_EXECUTE) {
+        flags.insert(PageTableFlags::NO_EXECUTE);
+    }
+    flags
+}
+
+// 递归遍历一级页表，对每个叶子映射调用 f
+unsafe fn walk_table(
+    table: &PageTable,
+    level: u8,
+    base: u64,
+    parent: PageTableFlags,
+    f: &mut impl FnMut(Mapping),
+) {
+    let entry_size = 4096u64 << (9 * (level as u64 - 1));
+    for (index, entry) in table.iter().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        // 规范地址：第 47 位为 1 时高位需要符号扩展
+        let virt = VirtAddr::new_truncate(base + index as u64 * entry_size);
+        let mut flags = combine(parent, flags);
+        if level == 1 {
+            flags.remove(PageTableFlags::HUGE_PAGE); // 1 级页表项的第 7 位是 PAT
+        }
+        let is_leaf = level == 1 || (level < 4 && flags.contains(PageTableFlags::HUGE_PAGE));
+        if is_leaf {
+            f(Mapping {
+                virt_start: virt,
+                phys_start: entry.addr(),
+                size: entry_size,
+                flags,
+            });
+        } else {
+            walk_table(table_at(entry.addr()), level - 1, virt.as_u64(), flags, f);
+        }
+    }
+}
+
+/// 从 CR3 开始遍历四级页表，按地址顺序对每段合并后的映射调用 `f`
+pub fn walk(mut f: impl FnMut(Mapping)) {
+    let (level_4_frame, _) = Cr3::read();
+    let all = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let mut current: Option<Mapping> = None;
+    unsafe {
+        walk_table(table_at(level_4_frame.start_address()), 4, 0, all, &mut |mapping| {
+            match current.as_mut() {
+                Some(range) if range.can_merge(&mapping) => range.size += mapping.size,
+                _ => {
+                    if let Some(range) = current.replace(mapping) {
+                        f(range);
+                    }
+                }
+            }
+        });
+    }
+    if let Some(range) = current {
+        f(range);
+    }
+}
+
+/// 通过串口输出当前地址空间的所有映射
+pub fn dump() {
+    serial_println!("page table dump begin");
+    walk(|mapping| {
+        serial_println!("{}", mapping);
+    });
+    serial_println!("page table dump end");
+}
+
+/// 返回 `addr` 所在页面的有效标志位，未映射时返回 `None`
+pub fn flags_of(addr: VirtAddr) -> Option<PageTableFlags> {
+    let (level_4_frame, _) = Cr3::read();
+    let indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut table = unsafe { table_at(level_4_frame.start_address()) };
+    let mut flags = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    for (depth, index) in indexes.into_iter().enumerate() {
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        flags = combine(flags, entry.flags());
+        if depth == 3 {
+            flags.remove(PageTableFlags::HUGE_PAGE); // 1 级页表项的第 7 位是 PAT
+            return Some(flags);
+        }
+        if depth > 0 && entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Some(flags);
+        }
+        table = unsafe { table_at(entry.addr()) };
+    }
+    unreachable!()
+}
+
+/// 断言 `addr` 已映射，并且设置了 `set` 中的所有标志位、没有设置 `clear` 中的任何标志位
+#[track_caller]
+pub fn assert_flags(addr: VirtAddr, set: PageTableFlags, clear: PageTableFlags) {
+    let flags = flags_of(addr).unwrap_or_else(|| panic!("{:?} is not mapped", addr));
+    assert!(
+        flags.contains(set) && !flags.intersects(clear),
+        "{:?} has flags {:?}, expected {:?} set and {:?} clear",
+        addr,
+        flags,
+        set,
+        clear
+    );
+}
+
+#[test_case]
+fn test_heap_is_writable() {
+    let heap = VirtAddr::new(crate::allocator::HEAP_START as u64);
+    assert_flags(
+        heap,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        PageTableFlags::USER_ACCESSIBLE,
+    );
+}
+
+#[test_case]
+fn test_code_is_executable() {
+    let code = VirtAddr::from_ptr(flags_of as *const ());
+    assert_flags(code, PageTableFlags::PRESENT, PageTableFlags::NO_EXECUTE);
+}
+
+#[test_case]
+fn test_walk_finds_heap_mapping() {
+    let heap = VirtAddr::new(crate::allocator::HEAP_START as u64);
+    let mut found = false;
+    walk(|mapping| {
+        if mapping.virt_start <= heap && heap < mapping.virt_end() {
+            found = true;
+        }
+    });
+    assert!(found);
+}
@@ -0,0 +1,110 @@
+use super::{split_and_update_flags, with_mapper};
+use x86_64::registers::control::{Cr0, Cr0Flags, Efer, EferFlags};
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+// 由 linker.ld 导出的段边界，都按 4 KiB 对齐
+extern "C" {
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __data_start: u8;
+    static __data_end: u8;
+}
+
+/// 内核映像中的一段及其应有的页面权限
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    pub name: &'static str,
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub flags: PageTableFlags,
+}
+
+/// 返回内核映像的各段：`.text` 只读可执行，`.rodata` 只读不可执行，
+/// `.data` 和 `.bss` 可写不可执行
+pub fn sections() -> [Section; 3] {
+    let addr = |symbol: *const u8| VirtAddr::from_ptr(symbol);
+    let present = PageTableFlags::PRESENT;
+    let nx = PageTableFlags::NO_EXECUTE;
+    [
+        Section {
+            name: ".text",
+            start: addr(&raw const __text_start),
+            end: addr(&raw const __text_end),
+            flags: present,
+        },
+        Section {
+            name: ".rodata",
+            start: addr(&raw const __rodata_start),
+            end: addr(&raw const __rodata_end),
+            flags: present | nx,
+        },
+        Section {
+            name: ".data/.bss",
+            start: addr(&raw const __data_start),
+            end: addr(&raw const __data_end),
+            flags: present | PageTableFlags::WRITABLE | nx,
+        },
+    ]
+}
+
+/// 启用 NX 位（EFER.NXE）和内核态写保护（CR0.WP）
+///
+/// 必须在页表中出现 NO_EXECUTE 标志之前调用，否则该位会被当作保留位。
+pub fn enable_enforcement() {
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+}
+
+/// 按 `sections` 重新设置内核映像每个页面的权限，使任何页面都不同时可写和可执行
+pub fn protect_kernel_image() {
+    with_mapper(|mapper, frame_allocator| {
+        for section in sections() {
+            let start = Page::containing_address(section.start);
+            let end = Page::containing_address(section.end - 1u64);
+            for page in Page::range_inclusive(start, end) {
+                unsafe { split_and_update_flags(mapper, page, section.flags, frame_allocator) }
+                    .unwrap_or_else(|err| {
+                        panic!("failed to protect {} page {:?}: {:?}", section.name, page, err)
+                    });
+            }
+        }
+    });
+}
+
+#[test_case]
+fn test_sections_have_expected_permissions() {
+    use super::inspect::assert_flags;
+    static RODATA: [u8; 4] = [1, 2, 3, 4];
+    static mut DATA: u8 = 0;
+
+    let text = VirtAddr::from_ptr(sections as *const ());
+    assert_flags(
+        text,
+        PageTableFlags::PRESENT,
+        PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+    );
+    assert_flags(
+        VirtAddr::from_ptr(&RODATA),
+        PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE,
+        PageTableFlags::WRITABLE,
+    );
+    assert_flags(
+        VirtAddr::from_ptr(&raw const DATA),
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        PageTableFlags::empty(),
+    );
+}
+
+#[test_case]
+fn test_sections_do_not_overlap() {
+    let sections = sections();
+    for pair in sections.windows(2) {
+        assert!(pair[0].start < pair[0].end);
+        assert!(pair[0].end <= pair[1].start);
+    }
+}
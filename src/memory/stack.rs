@@ -0,0 +1,111 @@
+use super::vmalloc::{self, VmArea};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::structures::paging::Page;
+use x86_64::VirtAddr;
+
+/// 从内核虚拟地址空间分配的栈，下方有一个未映射的保护页
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStack {
+    pub name: &'static str,
+    area: VmArea,
+}
+
+impl KernelStack {
+    /// 栈顶地址（栈向下增长，这是初始的栈指针）
+    pub fn top(&self) -> VirtAddr {
+        self.area.end()
+    }
+
+    /// 栈底地址，即最低的可用地址
+    pub fn bottom(&self) -> VirtAddr {
+        self.area.start
+    }
+
+    /// 栈下方的保护页
+    pub fn guard_page(&self) -> Page {
+        self.area.guard_page()
+    }
+}
+
+// 所有已分配的栈，用于在缺页时判断是哪个栈溢出了
+static STACKS: Mutex<Vec<KernelStack>> = Mutex::new(Vec::new());
+
+/// bootloader 分配的启动栈在溢出报告中的名称
+pub const BOOT_STACK_NAME: &str = "boot";
+// 启动栈保护页的地址，0 表示没有找到
+static BOOT_STACK_GUARD: AtomicU64 = AtomicU64::new(0);
+// 向下查找启动栈的保护页时最多检查的页数
+const BOOT_STACK_MAX_PAGES: u64 = 4096;
+
+/// 登记 bootloader 分配的启动栈下方的保护页，需要在内存初始化之后、仍在启动栈上时调用
+///
+/// bootloader 在启动栈的最低处留了一个未映射的页面，从当前栈指针向下找到的
+/// 第一个未映射页面就是它。
+pub fn init() {
+    let marker = 0u8;
+    let mut page: Page = Page::containing_address(VirtAddr::from_ptr(&marker));
+    for _ in 0..BOOT_STACK_MAX_PAGES {
+        page -= 1;
+        if super::translate_lockless(page.start_address()).is_none() {
+            BOOT_STACK_GUARD.store(page.start_address().as_u64(), Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+/// 分配一个名为 `name`、大小为 `pages` 页的内核栈
+pub fn allocate_stack(name: &'static str, pages: u64) -> Option<KernelStack> {
+    let start = vmalloc::vmalloc((pages * 4096) as usize)?;
+    let area = vmalloc::find_area(start)?;
+    let stack = KernelStack { name, area };
+    STACKS.lock().push(stack);
+    Some(stack)
+}
+
+/// 释放由 `allocate_stack` 分配的栈
+///
+/// # Safety
+///
+/// 调用者必须保证该栈已不再被任何 CPU 或 TSS 使用。
+pub unsafe fn free_stack(stack: KernelStack) {
+    STACKS.lock().retain(|s| s.bottom() != stack.bottom());
+    vmalloc::vfree(stack.bottom());
+}
+
+/// 如果 `addr` 位于某个内核栈的保护页中，返回该栈的名称
+///
+/// 该函数会在异常处理函数中调用，所以只尝试获取锁，获取失败时返回 `None`。
+pub fn guard_page_owner(addr: VirtAddr) -> Option<&'static str> {
+    let page = Page::containing_address(addr);
+    let boot_guard = BOOT_STACK_GUARD.load(Ordering::Relaxed);
+    if boot_guard != 0 && page.start_address().as_u64() == boot_guard {
+        return Some(BOOT_STACK_NAME);
+    }
+    let stacks = STACKS.try_lock()?;
+    stacks
+        .iter()
+        .find(|stack| stack.guard_page() == page)
+        .map(|stack| stack.name)
+}
+
+#[test_case]
+fn test_stack_guard_page_is_registered() {
+    let stack = allocate_stack("test", 2).expect("stack allocation failed");
+    assert_eq!(stack.top() - stack.bottom(), 2 * 4096);
+    assert_eq!(guard_page_owner(stack.bottom() - 1u64), Some("test"));
+    assert_eq!(guard_page_owner(stack.bottom()), None);
+    unsafe { free_stack(stack) };
+    assert_eq!(guard_page_owner(stack.bottom() - 1u64), None);
+}
+
+#[test_case]
+fn test_boot_stack_guard_page_is_registered() {
+    // 测试运行在启动栈上，保护页位于当前栈帧下方且没有映射
+    let marker = 0u8;
+    let guard = VirtAddr::new(BOOT_STACK_GUARD.load(Ordering::Relaxed));
+    assert!(!guard.is_null() && guard < VirtAddr::from_ptr(&marker));
+    assert!(super::translate_lockless(guard).is_none());
+    assert_eq!(guard_page_owner(guard + 8u64), Some(BOOT_STACK_NAME));
+}
@@ -0,0 +1,296 @@
+use super::{demand, map_page, map_range, unmap_range, with_mapper};
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, PhysFrame, Size2MiB, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+// 内核虚拟地址分配器管理的地址范围
+pub const VMALLOC_START: u64 = 0x_6666_0000_0000;
+pub const VMALLOC_SIZE: u64 = 64 << 30; // 64 GiB
+
+const PAGE_SIZE: u64 = 4096;
+
+/// 一段虚拟地址区域的用途
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaKind {
+    /// 由 vmalloc 映射了新分配的物理帧，释放时归还这些帧
+    Allocated,
+    /// 映射到设备的物理地址，释放时只取消映射
+    Mmio,
+    /// 只保留了地址范围，由调用者自行映射
+    Reserved,
+    /// 按需分配：首次访问时由缺页处理函数映射清零的帧，释放时归还这些帧
+    Lazy,
+}
+
+/// 一段已分配的虚拟地址区域，不包含其下方的保护页
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmArea {
+    pub start: VirtAddr,
+    pub pages: u64,
+    pub kind: AreaKind,
+}
+
+impl VmArea {
+    /// 区域结束地址（不包含）
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.pages * PAGE_SIZE
+    }
+
+    /// 区域下方未映射的保护页
+    pub fn guard_page(&self) -> Page {
+        Page::containing_address(self.start - PAGE_SIZE)
+    }
+}
+
+// 空闲的虚拟地址范围按起始地址排序，区域的键为其起始地址（不含保护页）
+struct VirtualAllocator {
+    free: Vec<(u64, u64)>, // (起始地址, 页数)
+    areas: BTreeMap<u64, VmArea>,
+}
+
+impl VirtualAllocator {
+    fn new() -> Self {
+        VirtualAllocator {
+            free: vec![(VMALLOC_START, VMALLOC_SIZE / PAGE_SIZE)],
+            areas: BTreeMap::new(),
+        }
+    }
+
+    // 首次适配：为按 `align` 对齐的区域和其下方的一个保护页找到空闲地址
+    fn reserve(&mut self, pages: u64, align: u64, kind: AreaKind) -> Option<VmArea> {
+        let (index, start) = self.free.iter().enumerate().find_map(|(index, &(s, len))| {
+            let start = (s + PAGE_SIZE + align - 1) & !(align - 1);
+            (start + pages * PAGE_SIZE <= s + len * PAGE_SIZE).then_some((index, start))
+        })?;
+
+        // 把空闲范围中保护页之前和区域之后剩余的部分放回空闲列表
+        let (free_start, len) = self.free.remove(index);
+        let free_end = free_start + len * PAGE_SIZE;
+        let guard = start - PAGE_SIZE;
+        let end = start + pages * PAGE_SIZE;
+        if end < free_end {
+            self.free.insert(index, (end, (free_end - end) / PAGE_SIZE));
+        }
+        if guard > free_start {
+            self.free.insert(index, (free_start, (guard - free_start) / PAGE_SIZE));
+        }
+
+        let area = VmArea {
+            start: VirtAddr::new(start),
+            pages,
+            kind,
+        };
+        self.areas.insert(area.start.as_u64(), area);
+        Some(area)
+    }
+
+    // 把区域连同保护页放回空闲列表，并与相邻的空闲范围合并
+    fn release(&mut self, start: VirtAddr) -> Option<VmArea> {
+        let area = self.areas.remove(&start.as_u64())?;
+        let range_start = area.start.as_u64() - PAGE_SIZE;
+        let range_pages = area.pages + 1;
+
+        let index = self.free.partition_point(|&(s, _)| s < range_start);
+        self.free.insert(index, (range_start, range_pages));
+        // 与后一个空闲范围合并
+        if index + 1 < self.free.len() {
+            let (start, len) = self.free[index];
+            if start + len * PAGE_SIZE == self.free[index + 1].0 {
+                self.free[index].1 += self.free[index + 1].1;
+                self.free.remove(index + 1);
+            }
+        }
+        // 与前一个空闲范围合并
+        if index > 0 {
+            let (start, len) = self.free[index - 1];
+            if start + len * PAGE_SIZE == self.free[index].0 {
+                self.free[index - 1].1 += self.free[index].1;
+                self.free.remove(index);
+            }
+        }
+        Some(area)
+    }
+
+    fn find(&self, addr: VirtAddr) -> Option<VmArea> {
+        let (_, area) = self.areas.range(..=addr.as_u64()).next_back()?;
+        if addr < area.end() {
+            Some(*area)
+        } else {
+            None
+        }
+    }
+}
+
+lazy_static! {
+    static ref VMALLOC: Mutex<VirtualAllocator> = Mutex::new(VirtualAllocator::new());
+}
+
+fn pages_for(size: usize) -> u64 {
+    (size as u64).div_ceil(PAGE_SIZE).max(1)
+}
+
+/// 保留一段至少 `size` 字节的虚拟地址范围，但不建立映射
+pub fn reserve(size: usize) -> Option<VmArea> {
+    VMALLOC.lock().reserve(pages_for(size), PAGE_SIZE, AreaKind::Reserved)
+}
+
+/// 分配一段至少 `size` 字节的虚拟内存，并映射新分配的物理帧
+///
+/// 不小于 2 MiB 的区域按 2 MiB 对齐，尽量使用大页映射。
+pub fn vmalloc(size: usize) -> Option<VirtAddr> {
+    let pages = pages_for(size);
+    let align = if pages * PAGE_SIZE >= Size2MiB::SIZE {
+        Size2MiB::SIZE
+    } else {
+        PAGE_SIZE
+    };
+    let area = VMALLOC.lock().reserve(pages, align, AreaKind::Allocated)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    let mapped = with_mapper(|mapper, frame_allocator| {
+        map_range(mapper, area.start, pages * PAGE_SIZE, flags, frame_allocator)
+    });
+    if mapped.is_err() {
+        // map_range 失败时已经撤销了映射，只需归还地址范围
+        VMALLOC.lock().release(area.start);
+        return None;
+    }
+    Some(area.start)
+}
+
+/// 分配一段至少 `size` 字节的虚拟内存，物理帧在首次访问时才分配
+pub fn vmalloc_lazy(size: usize) -> Option<VirtAddr> {
+    let pages = pages_for(size);
+    let area = VMALLOC.lock().reserve(pages, PAGE_SIZE, AreaKind::Lazy)?;
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    demand::register_lazy_region(area.start, pages * PAGE_SIZE, flags);
+    Some(area.start)
+}
+
+/// 把一段物理地址（通常是设备的 MMIO 寄存器）映射到内核虚拟地址空间
+///
+/// 返回的地址与 `phys` 在页内的偏移相同。
+pub fn ioremap(phys: PhysAddr, size: usize) -> Option<VirtAddr> {
+    let offset = phys.as_u64() % PAGE_SIZE;
+    let pages = pages_for(offset as usize + size);
+    let area = VMALLOC.lock().reserve(pages, PAGE_SIZE, AreaKind::Mmio)?;
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_CACHE;
+    let first_frame = PhysFrame::containing_address(phys);
+
+    let mapped = with_mapper(|mapper, frame_allocator| {
+        for (index, page) in area_pages(&area).enumerate() {
+            let frame = first_frame + index as u64;
+            // 设备内存不属于帧分配器，不会与其他映射冲突
+            unsafe { map_page(mapper, page, frame, flags, frame_allocator) }?;
+        }
+        Ok::<(), MapToError<Size4KiB>>(())
+    });
+
+    if mapped.is_err() {
+        unmap_area(&area);
+        VMALLOC.lock().release(area.start);
+        return None;
+    }
+    Some(area.start + offset)
+}
+
+/// 释放由 `vmalloc`、`vmalloc_lazy`、`ioremap` 或 `reserve` 得到的区域
+///
+/// 区域中已映射的页面会被取消映射，`vmalloc` 分配的物理帧会被归还。
+pub fn vfree(addr: VirtAddr) {
+    let start = addr.align_down(PAGE_SIZE);
+    let area = VMALLOC
+        .lock()
+        .find(start)
+        .filter(|area| area.start == start)
+        .unwrap_or_else(|| panic!("vfree: {:?} is not the start of an area", addr));
+    if area.kind == AreaKind::Lazy {
+        demand::unregister_lazy_region(area.start);
+    }
+    unmap_area(&area);
+    VMALLOC.lock().release(area.start);
+}
+
+/// 返回包含 `addr` 的区域
+pub fn find_area(addr: VirtAddr) -> Option<VmArea> {
+    VMALLOC.lock().find(addr)
+}
+
+// 区域中的所有页面
+fn area_pages(area: &VmArea) -> impl Iterator<Item = Page> {
+    let start = Page::containing_address(area.start);
+    Page::range(start, start + area.pages)
+}
+
+// 取消区域中所有页面的映射，`vmalloc` 和 `vmalloc_lazy` 分配的帧会被归还
+fn unmap_area(area: &VmArea) {
+    let free_frames = matches!(area.kind, AreaKind::Allocated | AreaKind::Lazy);
+    with_mapper(|mapper, frame_allocator| {
+        unmap_range(mapper, area.start, area.pages * PAGE_SIZE, free_frames, frame_allocator)
+    });
+}
+
+#[test_case]
+fn test_vmalloc_maps_and_frees() {
+    use super::translate_addr;
+
+    let addr = vmalloc(3 * PAGE_SIZE as usize).expect("vmalloc failed");
+    let area = find_area(addr).unwrap();
+    assert_eq!(area.pages, 3);
+
+    let ptr: *mut u64 = addr.as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(42);
+        ptr.add(1023).write_volatile(43); // 第二页
+    }
+    assert_eq!(unsafe { ptr.read_volatile() }, 42);
+
+    with_mapper(|mapper, _| {
+        // 保护页必须未映射
+        assert!(translate_addr(mapper, area.guard_page().start_address()).is_none());
+        assert!(translate_addr(mapper, addr).is_some());
+    });
+
+    vfree(addr);
+    assert!(find_area(addr).is_none());
+    with_mapper(|mapper, _| assert!(translate_addr(mapper, addr).is_none()));
+}
+
+#[test_case]
+fn test_large_vmalloc_uses_huge_pages() {
+    use super::inspect::flags_of;
+
+    let size = 2 * Size2MiB::SIZE as usize + PAGE_SIZE as usize;
+    let addr = vmalloc(size).expect("vmalloc failed");
+    assert!(addr.is_aligned(Size2MiB::SIZE));
+    assert!(flags_of(addr).unwrap().contains(PageTableFlags::HUGE_PAGE));
+    // 末尾不足 2 MiB 的部分使用 4 KiB 页面
+    let tail = addr + 2 * Size2MiB::SIZE;
+    assert!(!flags_of(tail).unwrap().contains(PageTableFlags::HUGE_PAGE));
+
+    let ptr: *mut u8 = addr.as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(1);
+        ptr.add(size - 1).write_volatile(2);
+    }
+    vfree(addr);
+    assert!(flags_of(addr).is_none());
+}
+
+#[test_case]
+fn test_areas_are_separated_by_guard_pages() {
+    let first = vmalloc(1).expect("vmalloc failed");
+    let second = vmalloc(1).expect("vmalloc failed");
+    assert!(second >= first + 2 * PAGE_SIZE || first >= second + 2 * PAGE_SIZE);
+    vfree(first);
+    vfree(second);
+}
@@ -26,6 +26,19 @@ pub fn _print(args: ::core::fmt::Arguments) {
     });
 }
 
+/// 在不能等待锁的上下文（例如 NMI）中输出，串口的锁被占用时强制解锁
+///
+/// 被打断的代码可能正持有 SERIAL1 的锁，只应在报告死锁这类无法恢复的情况时使用。
+#[doc(hidden)]
+pub fn _print_emergency(args: ::core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    if SERIAL1.try_lock().is_none() {
+        unsafe { SERIAL1.force_unlock() };
+    }
+    let _ = SERIAL1.lock().write_fmt(args);
+}
+
 /// Prints to the host through the serial interface.
 #[macro_export]
 macro_rules! serial_print {
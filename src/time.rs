@@ -0,0 +1,126 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::port::Port;
+
+pub mod apic_timer;
+pub mod clockevent;
+pub mod hpet;
+pub mod tsc;
+
+/// PIT 的输入时钟频率（Hz）
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+/// 内核默认的时钟中断频率
+pub const DEFAULT_TICK_HZ: u32 = 1000;
+
+// 时钟中断的次数和实际频率
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static TICK_HZ: AtomicU32 = AtomicU32::new(0);
+
+/// 把 PIT 通道 0 设置为每秒产生 `hz` 次中断
+///
+/// 分频系数只能是整数，所以实际频率可能与 `hz` 略有差别，可以通过 `frequency` 读取。
+pub fn init_pit(hz: u32) {
+    let divisor = (PIT_FREQUENCY / hz.max(1)).clamp(1, 0xffff);
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel0: Port<u8> = Port::new(0x40);
+    unsafe {
+        // 通道 0，先低字节后高字节，模式 3（方波），二进制计数
+        command.write(0x36);
+        channel0.write(divisor as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+    TICK_HZ.store(PIT_FREQUENCY / divisor, Ordering::Relaxed);
+}
+
+/// 由时钟中断调用，使计数加一
+///
+/// 计数必须在中断中更新：`delay_ms` 等轮询计数的代码可能正运行在下半部中。
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 启动以来的时钟中断次数
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// 时钟中断的实际频率（Hz），PIT 未初始化时为 0
+pub fn frequency() -> u32 {
+    TICK_HZ.load(Ordering::Relaxed)
+}
+
+/// 启动以来经过的时间，精度为一个时钟周期
+pub fn uptime() -> Duration {
+    match u64::from(frequency()) {
+        0 => Duration::ZERO,
+        hz => {
+            let ticks = ticks();
+            Duration::from_secs(ticks / hz) + Duration::from_nanos((ticks % hz) * 1_000_000_000 / hz)
+        }
+    }
+}
+
+/// 忙等待至少 `ms` 毫秒，需要开中断
+pub fn delay_ms(ms: u64) {
+    debug_assert!(
+        x86_64::instructions::interrupts::are_enabled(),
+        "delay_ms with interrupts disabled never returns"
+    );
+    // 向上取整，并多等一个周期，因为当前周期可能即将结束
+    let hz = u64::from(frequency());
+    let wait = (ms * hz).div_ceil(1000) + 1;
+    let target = ticks() + wait;
+    while ticks() < target {
+        core::hint::spin_loop();
+    }
+}
+
+/// 以 PIT 为参考校准 TSC 和 APIC 定时器的频率
+///
+/// 需要在 PIT 初始化并开中断之后调用。Local APIC 可用时，校准完成后把 APIC 定时器
+/// 注册为时钟事件设备。
+pub fn calibrate() {
+    let hz = u64::from(frequency());
+    if hz == 0 {
+        return;
+    }
+    // 测量 50 毫秒，从一个时钟周期的边界开始
+    let window = (hz / 20).max(1);
+    let start = ticks();
+    while ticks() == start {
+        core::hint::spin_loop();
+    }
+    let local_apic = crate::interrupts::apic::local_apic();
+    let tsc_start = tsc::read();
+    if let Some(local_apic) = &local_apic {
+        apic_timer::begin_calibration(local_apic);
+    }
+    let target = start + 1 + window;
+    while ticks() < target {
+        core::hint::spin_loop();
+    }
+    let elapsed = Duration::from_nanos(window * 1_000_000_000 / hz);
+    if let Some(local_apic) = &local_apic {
+        apic_timer::finish_calibration(local_apic, elapsed);
+    }
+    tsc::set_frequency((tsc::read() - tsc_start) * hz / window);
+
+    if local_apic.is_some() {
+        clockevent::register_device(&apic_timer::APIC_TIMER);
+    }
+}
+
+#[test_case]
+fn test_ticks_advance() {
+    let start = ticks();
+    let uptime = self::uptime();
+    delay_ms(20);
+    assert!(ticks() >= start + u64::from(frequency()) / 50);
+    assert!(self::uptime() >= uptime + Duration::from_millis(20));
+}
+
+#[test_case]
+fn test_pit_frequency_is_close_to_requested() {
+    let hz = frequency();
+    assert!(hz.abs_diff(DEFAULT_TICK_HZ) <= 1, "PIT runs at {} Hz", hz);
+}
@@ -0,0 +1,215 @@
+use super::clockevent::{self, ClockEventDevice, ClockEventError, Features};
+use super::tsc;
+use crate::interrupts::apic::{self, LocalApic};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+/// APIC 定时器使用的中断向量，紧接在传统 IRQ 之后
+pub const VECTOR: u8 = 0x30;
+
+const DIVIDE_BY_16: u32 = 0b0011;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_PERIODIC: u32 = 0b01 << 17;
+const LVT_TSC_DEADLINE: u32 = 0b10 << 17;
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
+
+// 每毫秒的 APIC 定时器计数（16 分频），0 表示尚未校准
+static COUNTS_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+/// APIC 定时器每毫秒的计数，尚未校准时为 0
+pub fn counts_per_ms() -> u64 {
+    COUNTS_PER_MS.load(Ordering::Relaxed)
+}
+
+/// 开始校准：让定时器从最大值开始单次倒数，不产生中断
+pub(super) fn begin_calibration(local_apic: &LocalApic) {
+    unsafe {
+        local_apic.write(LocalApic::TIMER_DIVIDE, DIVIDE_BY_16);
+        local_apic.write(LocalApic::LVT_TIMER, u32::from(VECTOR) | LVT_MASKED);
+        local_apic.write(LocalApic::TIMER_INITIAL_COUNT, u32::MAX);
+    }
+}
+
+/// 结束校准：根据经过的时间计算定时器的频率，然后停止定时器
+pub(super) fn finish_calibration(local_apic: &LocalApic, elapsed: Duration) {
+    let counted = u64::from(u32::MAX - local_apic.read(LocalApic::TIMER_CURRENT_COUNT));
+    unsafe { local_apic.write(LocalApic::TIMER_INITIAL_COUNT, 0) };
+    let per_ms = (u128::from(counted) * 1_000_000 / elapsed.as_nanos()) as u64;
+    COUNTS_PER_MS.store(per_ms, Ordering::Relaxed);
+}
+
+// 把一段时间换算成定时器的初始计数
+fn count_for(duration: Duration) -> Result<u32, ClockEventError> {
+    let count = duration.as_nanos() * u128::from(counts_per_ms()) / 1_000_000;
+    match u32::try_from(count) {
+        Ok(0) | Err(_) => Err(ClockEventError::OutOfRange),
+        Ok(count) => Ok(count),
+    }
+}
+
+/// Local APIC 定时器，支持 TSC-deadline 时单次触发使用该模式
+pub struct ApicTimer;
+
+/// 全局的 APIC 定时器设备
+pub static APIC_TIMER: ApicTimer = ApicTimer;
+
+impl ApicTimer {
+    fn local_apic(&self) -> Result<LocalApic, ClockEventError> {
+        match apic::local_apic() {
+            Some(local_apic) if counts_per_ms() != 0 => Ok(local_apic),
+            _ => Err(ClockEventError::NoDevice),
+        }
+    }
+
+    /// 在 TSC 到达 `deadline` 时产生一次事件
+    pub fn set_deadline(&self, deadline: u64) -> Result<(), ClockEventError> {
+        let local_apic = self.local_apic()?;
+        if !tsc::supports_deadline() {
+            return Err(ClockEventError::Unsupported);
+        }
+        unsafe {
+            local_apic.write(LocalApic::LVT_TIMER, u32::from(VECTOR) | LVT_TSC_DEADLINE);
+            // 切换到 TSC-deadline 模式后，写入 MSR 前需要保证 LVT 的写入已经生效
+            core::arch::x86_64::_mm_mfence();
+            Msr::new(IA32_TSC_DEADLINE).write(deadline);
+        }
+        Ok(())
+    }
+}
+
+impl ClockEventDevice for ApicTimer {
+    fn name(&self) -> &'static str {
+        if tsc::supports_deadline() {
+            "lapic-deadline"
+        } else {
+            "lapic"
+        }
+    }
+
+    fn features(&self) -> Features {
+        Features {
+            periodic: true,
+            oneshot: true,
+            deadline: tsc::supports_deadline(),
+        }
+    }
+
+    fn set_periodic(&self, period: Duration) -> Result<(), ClockEventError> {
+        let local_apic = self.local_apic()?;
+        let count = count_for(period)?;
+        unsafe {
+            local_apic.write(LocalApic::TIMER_DIVIDE, DIVIDE_BY_16);
+            local_apic.write(LocalApic::LVT_TIMER, u32::from(VECTOR) | LVT_PERIODIC);
+            local_apic.write(LocalApic::TIMER_INITIAL_COUNT, count);
+        }
+        Ok(())
+    }
+
+    fn set_oneshot(&self, delta: Duration) -> Result<(), ClockEventError> {
+        if tsc::supports_deadline() {
+            let cycles = tsc::cycles(delta).ok_or(ClockEventError::NoDevice)?;
+            if cycles == 0 {
+                return Err(ClockEventError::OutOfRange);
+            }
+            return self.set_deadline(tsc::read() + cycles);
+        }
+        let local_apic = self.local_apic()?;
+        let count = count_for(delta)?;
+        unsafe {
+            local_apic.write(LocalApic::TIMER_DIVIDE, DIVIDE_BY_16);
+            local_apic.write(LocalApic::LVT_TIMER, u32::from(VECTOR));
+            local_apic.write(LocalApic::TIMER_INITIAL_COUNT, count);
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        if let Some(local_apic) = apic::local_apic() {
+            unsafe {
+                local_apic.write(LocalApic::LVT_TIMER, u32::from(VECTOR) | LVT_MASKED);
+                local_apic.write(LocalApic::TIMER_INITIAL_COUNT, 0);
+                if tsc::supports_deadline() {
+                    Msr::new(IA32_TSC_DEADLINE).write(0);
+                }
+            }
+        }
+    }
+}
+
+extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::interrupts::stats::record(VECTOR);
+    clockevent::handle_event();
+    if let Some(local_apic) = apic::local_apic() {
+        local_apic.end_of_interrupt();
+    }
+    crate::interrupts::deferred::irq_exit();
+}
+
+/// 在 IDT 中设置 APIC 定时器的处理函数
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    idt[usize::from(VECTOR)].set_handler_fn(timer_interrupt_handler);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::delay_ms;
+    use core::sync::atomic::AtomicU64;
+
+    static FIRED: AtomicU64 = AtomicU64::new(0);
+
+    fn count_event() {
+        FIRED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // 用 APIC 定时器运行 `f`，返回期间产生的事件数量
+    fn count_events(f: impl FnOnce()) -> u64 {
+        FIRED.store(0, Ordering::Relaxed);
+        clockevent::register_device(&APIC_TIMER);
+        clockevent::set_event_handler(Some(count_event));
+        f();
+        clockevent::shutdown();
+        clockevent::set_event_handler(None);
+        FIRED.load(Ordering::Relaxed)
+    }
+
+    #[test_case]
+    fn test_calibrated() {
+        assert!(counts_per_ms() > 0);
+        assert!(tsc::frequency() > 0);
+    }
+
+    #[test_case]
+    fn test_oneshot_fires_once() {
+        let fired = count_events(|| {
+            clockevent::program_oneshot(Duration::from_millis(5)).unwrap();
+            delay_ms(30);
+        });
+        assert_eq!(fired, 1);
+    }
+
+    #[test_case]
+    fn test_periodic_fires_repeatedly() {
+        let fired = count_events(|| {
+            clockevent::program_periodic(Duration::from_millis(5)).unwrap();
+            delay_ms(50);
+        });
+        // 允许模拟器的时间误差
+        assert!((5..=15).contains(&fired), "periodic timer fired {} times", fired);
+    }
+
+    #[test_case]
+    fn test_deadline_mode() {
+        if !tsc::supports_deadline() {
+            return;
+        }
+        let fired = count_events(|| {
+            let deadline = tsc::read() + tsc::cycles(Duration::from_millis(5)).unwrap();
+            APIC_TIMER.set_deadline(deadline).unwrap();
+            delay_ms(30);
+        });
+        assert_eq!(fired, 1);
+    }
+}
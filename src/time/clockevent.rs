@@ -0,0 +1,105 @@
+use crate::interrupts::deferred::Work;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// 时钟事件设备支持的触发方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    pub periodic: bool,
+    pub oneshot: bool,
+    /// 单次触发使用绝对时间（TSC-deadline）实现
+    pub deadline: bool,
+}
+
+/// 编程时钟事件时可能出现的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockEventError {
+    /// 没有注册时钟事件设备
+    NoDevice,
+    /// 设备不支持请求的触发方式
+    Unsupported,
+    /// 间隔为零或超出设备的计数范围
+    OutOfRange,
+}
+
+/// 可编程的时钟事件设备，到期时调用 `handle_event`
+///
+/// 调度器和睡眠 API 只通过这个接口使用定时器，不需要关心具体的硬件。
+pub trait ClockEventDevice: Sync {
+    fn name(&self) -> &'static str;
+
+    fn features(&self) -> Features;
+
+    /// 按 `period` 周期性地产生事件
+    fn set_periodic(&self, period: Duration) -> Result<(), ClockEventError>;
+
+    /// 在 `delta` 之后产生一次事件
+    fn set_oneshot(&self, delta: Duration) -> Result<(), ClockEventError>;
+
+    /// 停止产生事件
+    fn shutdown(&self);
+}
+
+// 只在关中断时修改，避免与中断处理函数死锁
+static DEVICE: Mutex<Option<&'static dyn ClockEventDevice>> = Mutex::new(None);
+static HANDLER: Mutex<Option<fn()>> = Mutex::new(None);
+static EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// 注册系统的时钟事件设备，替换之前注册的设备
+pub fn register_device(device: &'static dyn ClockEventDevice) {
+    without_interrupts(|| {
+        if let Some(old) = DEVICE.lock().replace(device) {
+            old.shutdown();
+        }
+    });
+}
+
+/// 当前注册的时钟事件设备
+pub fn device() -> Option<&'static dyn ClockEventDevice> {
+    without_interrupts(|| *DEVICE.lock())
+}
+
+/// 设置事件到期时调用的函数，它作为下半部在开中断时运行
+pub fn set_event_handler(handler: Option<fn()>) {
+    without_interrupts(|| *HANDLER.lock() = handler);
+}
+
+/// 用注册的设备周期性地产生事件
+pub fn program_periodic(period: Duration) -> Result<(), ClockEventError> {
+    device().ok_or(ClockEventError::NoDevice)?.set_periodic(period)
+}
+
+/// 用注册的设备在 `delta` 之后产生一次事件
+pub fn program_oneshot(delta: Duration) -> Result<(), ClockEventError> {
+    device().ok_or(ClockEventError::NoDevice)?.set_oneshot(delta)
+}
+
+/// 停止注册的设备
+pub fn shutdown() {
+    if let Some(device) = device() {
+        device.shutdown();
+    }
+}
+
+/// 已经产生的事件数量
+pub fn events() -> u64 {
+    EVENTS.load(Ordering::Relaxed)
+}
+
+/// 时钟事件的下半部，调用 `set_event_handler` 设置的函数
+pub static EVENT_WORK: Work = Work::new("clockevent", run_event_handler);
+
+fn run_event_handler(_arg: u64) {
+    let handler = without_interrupts(|| *HANDLER.lock());
+    if let Some(handler) = handler {
+        handler();
+    }
+}
+
+/// 由设备的中断处理函数调用：计数后把事件处理函数交给下半部
+pub(crate) fn handle_event() {
+    EVENTS.fetch_add(1, Ordering::Relaxed);
+    let _ = EVENT_WORK.schedule(0); // 队列已满时丢弃这次事件，丢弃次数记录在统计中
+}
@@ -0,0 +1,125 @@
+use crate::acpi;
+use crate::memory::vmalloc;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::VirtAddr;
+
+const GENERAL_CAPABILITIES: u64 = 0x000;
+const GENERAL_CONFIG: u64 = 0x010;
+const MAIN_COUNTER: u64 = 0x0f0;
+
+const CONFIG_ENABLE: u64 = 1 << 0;
+
+const TIMER_INT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+const TIMER_VALUE_SET: u64 = 1 << 6;
+const TIMER_ROUTE_SHIFT: u64 = 9;
+
+// 映射后的 HPET 寄存器地址，0 表示没有 HPET
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+/// 高精度事件定时器（HPET），通过内存映射的寄存器访问
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    base: VirtAddr,
+}
+
+/// 查找并启用 HPET 的主计数器，不使用传统替换路由，不影响 PIT
+pub fn init() -> Option<Hpet> {
+    if let Some(hpet) = hpet() {
+        return Some(hpet);
+    }
+    let base = vmalloc::ioremap(acpi::hpet_address()?, 4096)?;
+    let hpet = Hpet { base };
+    unsafe { hpet.write(GENERAL_CONFIG, hpet.read(GENERAL_CONFIG) | CONFIG_ENABLE) };
+    BASE.store(base.as_u64(), Ordering::Release);
+    Some(hpet)
+}
+
+/// 已经初始化的 HPET
+pub fn hpet() -> Option<Hpet> {
+    match BASE.load(Ordering::Acquire) {
+        0 => None,
+        base => Some(Hpet {
+            base: VirtAddr::new(base),
+        }),
+    }
+}
+
+impl Hpet {
+    fn read(&self, register: u64) -> u64 {
+        unsafe { (self.base + register).as_ptr::<u64>().read_volatile() }
+    }
+
+    unsafe fn write(&self, register: u64, value: u64) {
+        (self.base + register).as_mut_ptr::<u64>().write_volatile(value);
+    }
+
+    fn timer_config(timer: u8) -> u64 {
+        0x100 + 0x20 * u64::from(timer)
+    }
+
+    fn timer_comparator(timer: u8) -> u64 {
+        0x108 + 0x20 * u64::from(timer)
+    }
+
+    /// 主计数器的频率（Hz）
+    pub fn frequency(&self) -> u64 {
+        let period_fs = self.read(GENERAL_CAPABILITIES) >> 32;
+        1_000_000_000_000_000 / period_fs.max(1)
+    }
+
+    /// 主计数器的当前值
+    pub fn counter(&self) -> u64 {
+        self.read(MAIN_COUNTER)
+    }
+
+    /// 比较器（定时器）的数量
+    pub fn timers(&self) -> u8 {
+        ((self.read(GENERAL_CAPABILITIES) >> 8) & 0x1f) as u8 + 1
+    }
+
+    /// `timer` 可以连接的 I/O APIC 输入，第 n 位表示 GSI n
+    pub fn route_capabilities(&self, timer: u8) -> u32 {
+        (self.read(Self::timer_config(timer)) >> 32) as u32
+    }
+
+    /// `timer` 是否支持周期模式
+    pub fn supports_periodic(&self, timer: u8) -> bool {
+        self.read(Self::timer_config(timer)) & TIMER_PERIODIC_CAPABLE != 0
+    }
+
+    /// 让 `timer` 每隔 `period` 在 GSI `gsi` 上产生一次边沿触发的中断
+    pub fn start_periodic(&self, timer: u8, period: Duration, gsi: u32) {
+        let period = (period.as_nanos() * u128::from(self.frequency()) / 1_000_000_000) as u64;
+        let config = self.read(Self::timer_config(timer)) & !(0x1f << TIMER_ROUTE_SHIFT);
+        let config = config
+            | TIMER_INT_ENABLE
+            | TIMER_PERIODIC
+            | TIMER_VALUE_SET
+            | u64::from(gsi) << TIMER_ROUTE_SHIFT;
+        unsafe {
+            // 设置 VALUE_SET 后，第一次写入比较器设置下一次触发的时间，第二次写入设置周期
+            self.write(Self::timer_config(timer), config);
+            self.write(Self::timer_comparator(timer), self.counter() + period);
+            self.write(Self::timer_comparator(timer), period);
+        }
+    }
+
+    /// 停止 `timer` 产生中断
+    pub fn stop(&self, timer: u8) {
+        let config = self.read(Self::timer_config(timer));
+        unsafe { self.write(Self::timer_config(timer), config & !TIMER_INT_ENABLE) };
+    }
+}
+
+#[test_case]
+fn test_hpet_counter_advances() {
+    // QEMU 默认提供 HPET
+    let hpet = init().expect("no HPET");
+    assert!(hpet.frequency() > 0);
+    let start = hpet.counter();
+    super::delay_ms(2);
+    assert!(hpet.counter() > start);
+}
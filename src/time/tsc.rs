@@ -0,0 +1,41 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+// 校准得到的 TSC 频率（Hz），0 表示尚未校准
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// 读取时间戳计数器
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// TSC 的频率（Hz），尚未校准时为 0
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+pub(super) fn set_frequency(hz: u64) {
+    FREQUENCY.store(hz, Ordering::Relaxed);
+}
+
+/// 处理器是否支持 Local APIC 的 TSC-deadline 模式
+pub fn supports_deadline() -> bool {
+    __cpuid(1).ecx & (1 << 24) != 0
+}
+
+/// 把一段时间换算成 TSC 周期数，尚未校准时返回 None
+pub fn cycles(duration: Duration) -> Option<u64> {
+    match frequency() {
+        0 => None,
+        hz => u64::try_from(duration.as_nanos() * u128::from(hz) / 1_000_000_000).ok(),
+    }
+}
+
+/// 把 TSC 周期数换算成时间，尚未校准时返回零
+pub fn to_duration(cycles: u64) -> Duration {
+    match frequency() {
+        0 => Duration::ZERO,
+        hz => Duration::from_nanos((u128::from(cycles) * 1_000_000_000 / u128::from(hz)) as u64),
+    }
+}
@@ -0,0 +1,328 @@
+use crate::interrupts::apic::{self, LocalApic};
+use crate::interrupts::nmi::Registers;
+use crate::memory;
+use crate::serial::_print_emergency;
+use crate::time::{self, hpet, tsc};
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+use x86_64::registers::model_specific::Msr;
+use x86_64::VirtAddr;
+
+/// 默认的判定时间：时钟计数停止这么久后认为处理器已经卡死
+pub const DEFAULT_THRESHOLD: Duration = Duration::from_secs(10);
+// 看门狗 NMI 的周期
+const PERIOD: Duration = Duration::from_secs(1);
+// 回溯输出的最大栈帧数
+const BACKTRACE_DEPTH: usize = 16;
+
+const IA32_PMC0: u32 = 0xc1;
+const IA32_PERFEVTSEL0: u32 = 0x186;
+const IA32_PERF_GLOBAL_STATUS: u32 = 0x38e;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38f;
+const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+// 计数内核态和用户态的未停机核心周期，溢出时产生中断
+const UNHALTED_CORE_CYCLES: u64 = 0x3c | 1 << 16 | 1 << 17 | 1 << 20 | 1 << 22;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+// 不支持全宽度写入时，写入 PMC 的值只有低 32 位有效，并按符号扩展
+const MAX_PERF_PERIOD: u64 = 0x7fff_ffff;
+
+// 使用的 HPET 比较器
+const HPET_TIMER: u8 = 0;
+
+/// 产生看门狗 NMI 的硬件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogSource {
+    /// Local APIC 的性能计数器中断
+    PerfCounter,
+    /// 通过 I/O APIC 以 NMI 方式投递的 HPET 比较器
+    Hpet,
+}
+
+/// 启动看门狗时可能出现的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogError {
+    /// TSC 或 APIC 还没有校准，无法确定 NMI 的周期
+    NotCalibrated,
+    /// 没有可以产生周期性 NMI 的硬件
+    NoSource,
+}
+
+const SOURCE_NONE: u8 = 0;
+const SOURCE_PERF: u8 = 1;
+const SOURCE_HPET: u8 = 2;
+
+static SOURCE: AtomicU8 = AtomicU8::new(SOURCE_NONE);
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PANIC_ON_LOCKUP: AtomicBool = AtomicBool::new(false);
+static THRESHOLD_NS: AtomicU64 = AtomicU64::new(DEFAULT_THRESHOLD.as_nanos() as u64);
+static PERF_PERIOD: AtomicU64 = AtomicU64::new(0);
+// 上一次认领 HPET NMI 时的 TSC
+static HPET_LAST_NMI: AtomicU64 = AtomicU64::new(0);
+
+// 上一次检查时的时钟计数，以及计数最后一次变化时的 TSC
+static LAST_TICKS: AtomicU64 = AtomicU64::new(0);
+static LAST_PROGRESS: AtomicU64 = AtomicU64::new(0);
+// 当前这次卡死是否已经报告过
+static REPORTED: AtomicBool = AtomicBool::new(false);
+static LOCKUPS: AtomicU64 = AtomicU64::new(0);
+
+macro_rules! emergency_println {
+    ($($arg:tt)*) => {
+        _print_emergency(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// 启动看门狗：优先使用性能计数器，没有时使用 HPET，需要在时间校准之后调用
+pub fn init() -> Result<WatchdogSource, WatchdogError> {
+    if tsc::frequency() == 0 {
+        return Err(WatchdogError::NotCalibrated);
+    }
+    let source = if start_perf_counter() {
+        WatchdogSource::PerfCounter
+    } else if start_hpet() {
+        WatchdogSource::Hpet
+    } else {
+        return Err(WatchdogError::NoSource);
+    };
+    enable();
+    Ok(source)
+}
+
+/// 当前产生看门狗 NMI 的硬件
+pub fn source() -> Option<WatchdogSource> {
+    match SOURCE.load(Ordering::Relaxed) {
+        SOURCE_PERF => Some(WatchdogSource::PerfCounter),
+        SOURCE_HPET => Some(WatchdogSource::Hpet),
+        _ => None,
+    }
+}
+
+/// 开始在每次 NMI 时检查时钟计数是否停止
+///
+/// `init` 成功时会调用它；没有周期性的 NMI 来源时，由其他 NMI 驱动检查。
+pub fn enable() {
+    touch();
+    ENABLED.store(true, Ordering::Release);
+}
+
+/// 停止检查，硬件仍然会产生 NMI
+pub fn disable() {
+    ENABLED.store(false, Ordering::Release);
+}
+
+/// 设置判定卡死的时间
+pub fn set_threshold(threshold: Duration) {
+    THRESHOLD_NS.store(threshold.as_nanos() as u64, Ordering::Relaxed);
+}
+
+pub fn threshold() -> Duration {
+    Duration::from_nanos(THRESHOLD_NS.load(Ordering::Relaxed))
+}
+
+/// 检测到卡死后是否 panic，默认只输出报告
+pub fn set_panic_on_lockup(panic: bool) {
+    PANIC_ON_LOCKUP.store(panic, Ordering::Relaxed);
+}
+
+/// 检测到的卡死次数
+pub fn lockups() -> u64 {
+    LOCKUPS.load(Ordering::Relaxed)
+}
+
+/// 重新开始计时，已知会长时间关中断的代码可以调用它避免误报
+pub fn touch() {
+    LAST_TICKS.store(time::ticks(), Ordering::Relaxed);
+    LAST_PROGRESS.store(tsc::read(), Ordering::Relaxed);
+    REPORTED.store(false, Ordering::Relaxed);
+}
+
+// 架构性能监控的版本，不支持或者不能计数核心周期时为 0
+fn perf_version() -> u32 {
+    if __cpuid(0).eax < 0xa {
+        return 0;
+    }
+    let leaf = __cpuid(0xa);
+    let counters = (leaf.eax >> 8) & 0xff;
+    // EBX 的第 0 位为 1 表示不支持未停机核心周期事件
+    if counters == 0 || leaf.ebx & 1 != 0 {
+        return 0;
+    }
+    leaf.eax & 0xff
+}
+
+fn start_perf_counter() -> bool {
+    let Some(local_apic) = apic::local_apic() else {
+        return false;
+    };
+    let version = perf_version();
+    if version == 0 {
+        return false;
+    }
+    // 核心周期的频率与 TSC 相近
+    let period = tsc::cycles(PERIOD).unwrap_or(0).min(MAX_PERF_PERIOD);
+    PERF_PERIOD.store(period, Ordering::Relaxed);
+    unsafe {
+        Msr::new(IA32_PERFEVTSEL0).write(0);
+        Msr::new(IA32_PMC0).write(period.wrapping_neg());
+        Msr::new(IA32_PERFEVTSEL0).write(UNHALTED_CORE_CYCLES);
+        if version >= 2 {
+            let mut global_ctrl = Msr::new(IA32_PERF_GLOBAL_CTRL);
+            global_ctrl.write(global_ctrl.read() | 1);
+        }
+        local_apic.write(LocalApic::LVT_PERFORMANCE, LVT_DELIVERY_NMI);
+    }
+    SOURCE.store(SOURCE_PERF, Ordering::Relaxed);
+    true
+}
+
+// 性能计数器溢出后重新装入周期；LVT 在投递后被自动屏蔽，需要取消屏蔽
+fn rearm_perf_counter() -> bool {
+    if perf_version() >= 2 {
+        let overflowed = unsafe { Msr::new(IA32_PERF_GLOBAL_STATUS).read() } & 1 != 0;
+        if !overflowed {
+            return false; // 不是看门狗的 NMI
+        }
+        unsafe { Msr::new(IA32_PERF_GLOBAL_OVF_CTRL).write(1) };
+    } else {
+        // 没有溢出状态寄存器：计数器从负数开始，溢出回绕之后最高位才会变为 0
+        let width = (__cpuid(0xa).eax >> 16) & 0xff;
+        let sign = 1u64 << (width.clamp(32, 64) - 1);
+        if unsafe { Msr::new(IA32_PMC0).read() } & sign != 0 {
+            return false; // 不是看门狗的 NMI
+        }
+    }
+    unsafe {
+        Msr::new(IA32_PMC0).write(PERF_PERIOD.load(Ordering::Relaxed).wrapping_neg());
+        if let Some(local_apic) = apic::local_apic() {
+            local_apic.write(LocalApic::LVT_PERFORMANCE, LVT_DELIVERY_NMI);
+        }
+    }
+    true
+}
+
+fn start_hpet() -> bool {
+    let Some(hpet) = hpet::init() else {
+        return false;
+    };
+    if !hpet.supports_periodic(HPET_TIMER) {
+        return false;
+    }
+    // 选一个没有被传统 IRQ 使用的 I/O APIC 输入，以 NMI 方式投递
+    let capabilities = hpet.route_capabilities(HPET_TIMER);
+    let gsi = (0..32)
+        .filter(|gsi| capabilities & (1 << gsi) != 0)
+        .find(|&gsi| !apic::is_legacy_gsi(gsi) && apic::route_gsi_as_nmi(gsi));
+    let Some(gsi) = gsi else {
+        return false;
+    };
+    HPET_LAST_NMI.store(tsc::read(), Ordering::Relaxed);
+    hpet.start_periodic(HPET_TIMER, PERIOD, gsi);
+    SOURCE.store(SOURCE_HPET, Ordering::Relaxed);
+    true
+}
+
+/// 由 NMI 处理函数调用，返回 true 表示这是看门狗的 NMI 或者已经报告了卡死
+pub(crate) fn handle_nmi(registers: &Registers) -> bool {
+    let ours = match SOURCE.load(Ordering::Relaxed) {
+        SOURCE_PERF => rearm_perf_counter(),
+        SOURCE_HPET => hpet_period_elapsed(),
+        _ => false,
+    };
+    if !ENABLED.load(Ordering::Acquire) {
+        return ours;
+    }
+    check(registers) || ours
+}
+
+// 边沿触发的 HPET 中断没有状态位可以确认来源，只有距离上一次认领已经过了
+// 一个周期时才认为是看门狗的 NMI，其余的交给通用的 NMI 处理
+fn hpet_period_elapsed() -> bool {
+    let Some(period) = tsc::cycles(PERIOD) else {
+        return false;
+    };
+    let now = tsc::read();
+    // 允许 NMI 的投递延迟带来 1/8 周期的误差
+    if now.wrapping_sub(HPET_LAST_NMI.load(Ordering::Relaxed)) < period - period / 8 {
+        return false;
+    }
+    HPET_LAST_NMI.store(now, Ordering::Relaxed);
+    true
+}
+
+// 时钟计数停止超过阈值时报告一次，返回是否报告了卡死
+fn check(registers: &Registers) -> bool {
+    let ticks = time::ticks();
+    let now = tsc::read();
+    if LAST_TICKS.swap(ticks, Ordering::Relaxed) != ticks {
+        LAST_PROGRESS.store(now, Ordering::Relaxed);
+        REPORTED.store(false, Ordering::Relaxed);
+        return false;
+    }
+    let stalled = tsc::to_duration(now.saturating_sub(LAST_PROGRESS.load(Ordering::Relaxed)));
+    if stalled < threshold() || REPORTED.swap(true, Ordering::Relaxed) {
+        return false;
+    }
+    LOCKUPS.fetch_add(1, Ordering::Relaxed);
+    report(registers, ticks, stalled);
+    if PANIC_ON_LOCKUP.load(Ordering::Relaxed) {
+        panic!("watchdog: hard lockup, ticks stalled for {:?}", stalled);
+    }
+    true
+}
+
+// 通过串口输出寄存器和栈回溯，不等待被卡住的代码可能持有的锁
+fn report(registers: &Registers, ticks: u64, stalled: Duration) {
+    emergency_println!(
+        "watchdog: hard lockup detected, ticks stuck at {} for {:?}",
+        ticks,
+        stalled
+    );
+    emergency_println!("{}", registers);
+    emergency_println!("backtrace:");
+    emergency_println!("  #0 {:#x}", registers.frame.instruction_pointer.as_u64());
+    // 沿着帧指针链回溯。NMI 中不能触发缺页（返回时会提前解除 NMI 屏蔽），
+    // 所以读取之前先通过页表确认栈帧所在的内存已映射。
+    let mut frame = registers.rbp;
+    for depth in 1..BACKTRACE_DEPTH {
+        let Some([next, return_address]) = read_stack_frame(frame) else {
+            break;
+        };
+        if return_address == 0 {
+            break;
+        }
+        emergency_println!("  #{} {:#x}", depth, return_address);
+        // 调用者的栈帧在更高的地址上
+        if next <= frame {
+            break;
+        }
+        frame = next;
+    }
+}
+
+// 读取 `frame` 处保存的上一个帧指针和返回地址，内存未映射时返回 None
+fn read_stack_frame(frame: u64) -> Option<[u64; 2]> {
+    if frame == 0 || !frame.is_multiple_of(8) {
+        return None;
+    }
+    let start = VirtAddr::try_new(frame).ok()?;
+    let last = VirtAddr::try_new(frame.checked_add(15)?).ok()?;
+    memory::translate_lockless(start)?;
+    memory::translate_lockless(last)?;
+    Some(unsafe { start.as_ptr::<[u64; 2]>().read_volatile() })
+}
+
+#[test_case]
+fn test_no_lockup_while_ticks_advance() {
+    set_threshold(Duration::from_millis(20));
+    enable();
+    let before = lockups();
+    // 时钟计数在前进，软件触发的 NMI 不应当报告卡死
+    for _ in 0..4 {
+        time::delay_ms(10);
+        unsafe { core::arch::asm!("int 2") };
+    }
+    disable();
+    set_threshold(DEFAULT_THRESHOLD);
+    assert_eq!(lockups(), before);
+}
@@ -0,0 +1,129 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use blog_os::allocator::{self, HEAP_SIZE};
+use blog_os::serial_println;
+use bootloader::{entry_point, BootInfo};
+use core::arch::x86_64::_rdtsc;
+use core::panic::PanicInfo;
+
+// 通过切换 `*-allocator` feature 运行本测试，比较各分配器的碎片率和吞吐量：
+// cargo test --test allocator_stress --no-default-features --features linked-list-allocator
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init(boot_info);
+    serial_println!("heap allocator design: {}", allocator::DESIGN);
+    test_main();
+
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// 一个简单的伪随机数生成器，使每次运行的分配序列相同
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+#[test_case]
+fn throughput() {
+    const ROUNDS: u64 = 10_000;
+    let mut seed = 0x2545_f491_4f6c_dd1d;
+
+    let start = unsafe { _rdtsc() };
+    for _ in 0..ROUNDS {
+        let size = (next_random(&mut seed) % 512 + 1) as usize;
+        let buffer: Vec<u8> = Vec::with_capacity(size);
+        core::hint::black_box(&buffer);
+    }
+    let cycles = unsafe { _rdtsc() } - start;
+
+    serial_println!();
+    serial_println!(
+        "[{}] {} alloc/free pairs, {} cycles per pair",
+        allocator::DESIGN,
+        ROUNDS,
+        cycles / ROUNDS
+    );
+}
+
+#[test_case]
+fn fragmentation() {
+    let mut seed = 0x9e37_79b9_7f4a_7c15;
+    let before = allocator::stats();
+
+    // 分配大量大小不一的对象，直到用掉大约一半的堆
+    let mut objects = Vec::with_capacity(512);
+    while allocator::stats().used < HEAP_SIZE / 2 && objects.len() < objects.capacity() {
+        let size = (next_random(&mut seed) % 256 + 8) as usize;
+        objects.push(Vec::<u8>::with_capacity(size));
+    }
+
+    // 释放其中一半，在堆中留下空洞
+    let mut index = 0;
+    objects.retain(|_| {
+        index += 1;
+        index % 2 == 0
+    });
+    let during = allocator::stats();
+
+    drop(objects);
+    let after = allocator::stats();
+
+    serial_println!();
+    serial_println!(
+        "[{}] before: {:?}, fragmentation {}%",
+        allocator::DESIGN,
+        before,
+        before.fragmentation_percent()
+    );
+    serial_println!(
+        "[{}] with holes: {:?}, fragmentation {}%",
+        allocator::DESIGN,
+        during,
+        during.fragmentation_percent()
+    );
+    serial_println!(
+        "[{}] after free: {:?}, fragmentation {}%",
+        allocator::DESIGN,
+        after,
+        after.fragmentation_percent()
+    );
+    // 所有对象都已释放，已用内存回到测试之前；bump 分配器只有在全部分配释放后才回收
+    #[cfg(not(feature = "bump-allocator"))]
+    assert_eq!(after.used, before.used);
+    // 链表分配器释放时合并相邻的空闲块，空洞重新连成一片
+    #[cfg(feature = "linked-list-allocator")]
+    assert!(after.largest_free_block >= before.largest_free_block);
+
+    // 释放空洞之后，比任何空洞都大的分配仍然能够成功
+    let size = during.largest_free_block + 1;
+    let mut large: Vec<u8> = Vec::with_capacity(size);
+    large.resize(size, 0xa5);
+    assert!(large.iter().all(|&b| b == 0xa5));
+}
+
+#[test_case]
+fn reuse_after_fragmentation() {
+    // 交替分配大小差异很大的对象，释放后堆必须仍能满足较大的分配
+    for round in 0..100 {
+        let small = Box::new([round as u8; 16]);
+        let large: Vec<u8> = Vec::with_capacity(HEAP_SIZE / 8);
+        assert_eq!(small[0], round as u8);
+        assert!(large.capacity() >= HEAP_SIZE / 8);
+    }
+}
@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{self, cow, vmalloc};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init(boot_info);
+    test_main();
+
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+const PAGES: u64 = 2;
+const SIZE: usize = 4096 * PAGES as usize;
+
+// 返回页面映射的帧
+fn frame_of(addr: VirtAddr) -> PhysFrame {
+    let phys = memory::with_mapper(|mapper, _| memory::translate_addr(mapper, addr))
+        .expect("page not mapped");
+    PhysFrame::containing_address(phys)
+}
+
+fn buffer(addr: VirtAddr) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(addr.as_mut_ptr(), SIZE) }
+}
+
+// 分配两个缓冲区并让第二个以写时复制的方式共享第一个
+fn shared_buffers() -> (VirtAddr, VirtAddr) {
+    let src = vmalloc::vmalloc(SIZE).expect("vmalloc failed");
+    let dst = vmalloc::vmalloc(SIZE).expect("vmalloc failed");
+    for (i, byte) in buffer(src).iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    cow::share(src, dst, PAGES).expect("share failed");
+    (src, dst)
+}
+
+#[test_case]
+fn shared_pages_are_read_only() {
+    let (src, dst) = shared_buffers();
+    assert_eq!(buffer(src), buffer(dst));
+    for addr in [src, dst] {
+        memory::inspect::assert_flags(
+            addr,
+            PageTableFlags::PRESENT,
+            PageTableFlags::WRITABLE,
+        );
+    }
+    assert_eq!(frame_of(src), frame_of(dst));
+    assert_eq!(cow::reference_count(frame_of(src)), 2);
+
+    vmalloc::vfree(dst);
+    assert_eq!(cow::reference_count(frame_of(src)), 1);
+    vmalloc::vfree(src);
+}
+
+#[test_case]
+fn copies_diverge_after_writes() {
+    let (src, dst) = shared_buffers();
+
+    buffer(src)[0] = 0xaa;
+    assert_eq!(buffer(dst)[0], 0, "write to the source leaked into the copy");
+    buffer(dst)[1] = 0xbb;
+    assert_eq!(buffer(src)[1], 1, "write to the copy leaked into the source");
+
+    // 第二页没有被写过，仍然共享
+    let second: u64 = 4096;
+    assert_eq!(frame_of(src + second), frame_of(dst + second));
+    buffer(dst)[second as usize] = 0xcc;
+    assert_eq!(buffer(src)[second as usize], 0);
+    assert_ne!(frame_of(src + second), frame_of(dst + second));
+
+    for i in 2..SIZE {
+        if i != second as usize {
+            assert_eq!(buffer(src)[i], buffer(dst)[i]);
+        }
+    }
+    vmalloc::vfree(src);
+    vmalloc::vfree(dst);
+}
+
+#[test_case]
+fn last_reference_is_made_writable_without_copy() {
+    let (src, dst) = shared_buffers();
+
+    buffer(dst)[0] = 1; // 复制出私有的帧
+    let frame = frame_of(src);
+    assert_eq!(cow::reference_count(frame), 1);
+    buffer(src)[0] = 2; // 源页面已是唯一引用，直接恢复可写
+    assert_eq!(frame_of(src), frame);
+    memory::inspect::assert_flags(src, PageTableFlags::WRITABLE, PageTableFlags::empty());
+    assert_eq!(buffer(dst)[0], 1);
+
+    vmalloc::vfree(src);
+    vmalloc::vfree(dst);
+}
@@ -0,0 +1,250 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::interrupts::exceptions::{self, ErrorCode, ExceptionInfo};
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::segmentation::{Segment, CS, SS};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::structures::idt::{DescriptorTable, InterruptStackFrame, SelectorErrorCode};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init(boot_info);
+    exceptions::set_exception_hook(Some(hook));
+    test_main();
+
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// 异常处理后继续执行的地址，0 表示返回到栈帧中原有的地址
+static RESUME_AT: AtomicU64 = AtomicU64::new(0);
+static LAST: Mutex<Option<ExceptionInfo>> = Mutex::new(None);
+
+// 记录异常并跳过触发它的指令
+fn hook(info: &ExceptionInfo, stack_frame: &mut InterruptStackFrame) -> bool {
+    *LAST.lock() = Some(*info);
+    let resume = RESUME_AT.swap(0, Ordering::SeqCst);
+    if resume != 0 {
+        unsafe {
+            stack_frame
+                .as_mut()
+                .update(|frame| frame.instruction_pointer = VirtAddr::new(resume));
+        }
+    }
+    true
+}
+
+fn last_exception() -> ExceptionInfo {
+    LAST.lock().take().expect("no exception was raised")
+}
+
+// 执行可能触发异常的指令，处理后从指令之后继续
+//
+// 返回 (指令地址, 指令之后的地址)。
+macro_rules! trigger {
+    ($instruction:literal $(, $($operands:tt)*)?) => {{
+        let fault: u64;
+        let landing: u64;
+        unsafe {
+            asm!(
+                "lea {landing}, [rip + 2f]",
+                "mov [{resume}], {landing}",
+                "lea {fault}, [rip + 3f]",
+                "3:",
+                $instruction,
+                "2:",
+                resume = in(reg) RESUME_AT.as_ptr(),
+                landing = out(reg) landing,
+                fault = out(reg) fault,
+                $($($operands)*)?
+            );
+        }
+        (fault, landing)
+    }};
+}
+
+// 读取 IDT 中某个向量的处理函数地址
+fn handler_address(vector: u8) -> u64 {
+    let idt = x86_64::instructions::tables::sidt();
+    let entry = (idt.base.as_u64() + 16 * u64::from(vector)) as *const u16;
+    unsafe {
+        u64::from(*entry)
+            | u64::from(*entry.add(3)) << 16
+            | u64::from(*(entry.add(4) as *const u32)) << 32
+    }
+}
+
+// 像 CPU 投递带错误码的异常那样构造栈帧并跳到处理函数，返回栈帧中的返回地址
+//
+// 这些异常（#TS、#NP、#AC、#CP）在内核态无法直接触发，而 `int n` 不会压入错误码。
+fn raise_with_error_code(vector: u8, error_code: u64) -> u64 {
+    let landing: u64;
+    unsafe {
+        asm!(
+            "lea {landing}, [rip + 2f]",
+            "mov {old_rsp}, rsp",
+            "and rsp, -16",
+            "push {ss}",
+            "push {old_rsp}",
+            "pushfq",
+            "cli",
+            "push {cs}",
+            "push {landing}",
+            "push {code}",
+            "jmp {handler}",
+            "2:",
+            landing = out(reg) landing,
+            old_rsp = out(reg) _,
+            ss = in(reg) u64::from(SS::get_reg().0),
+            cs = in(reg) u64::from(CS::get_reg().0),
+            code = in(reg) error_code,
+            handler = in(reg) handler_address(vector),
+        );
+    }
+    landing
+}
+
+fn assert_exception(vector: u8, error_code: ErrorCode, instruction_pointer: u64) {
+    let info = last_exception();
+    assert_eq!(info.vector, vector);
+    assert_eq!(info.error_code, error_code);
+    assert_eq!(info.instruction_pointer.as_u64(), instruction_pointer);
+}
+
+#[test_case]
+fn divide_error() {
+    let (fault, _) = trigger!("div ecx", in("ecx") 0u32, inout("eax") 1u32 => _, inout("edx") 0u32 => _);
+    assert_exception(0, ErrorCode::None, fault);
+}
+
+#[test_case]
+fn debug() {
+    // int1 是陷阱，栈帧中是下一条指令的地址
+    let (_, landing) = trigger!("int1");
+    assert_exception(1, ErrorCode::None, landing);
+}
+
+#[test_case]
+fn non_maskable_interrupt() {
+    let (_, landing) = trigger!("int 2");
+    assert_exception(2, ErrorCode::None, landing);
+}
+
+#[test_case]
+fn overflow() {
+    // 64 位模式下没有 into 指令
+    let (_, landing) = trigger!("int 4");
+    assert_exception(4, ErrorCode::None, landing);
+}
+
+#[test_case]
+fn bound_range_exceeded() {
+    // 64 位模式下没有 bound 指令
+    let (_, landing) = trigger!("int 5");
+    assert_exception(5, ErrorCode::None, landing);
+}
+
+#[test_case]
+fn invalid_opcode() {
+    let (fault, _) = trigger!("ud2");
+    assert_exception(6, ErrorCode::None, fault);
+}
+
+#[test_case]
+fn device_not_available() {
+    // CR0.TS 置位时执行 x87 指令
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED)) };
+    let (fault, _) = trigger!("fnop");
+    unsafe { Cr0::update(|flags| flags.remove(Cr0Flags::TASK_SWITCHED)) };
+    assert_exception(7, ErrorCode::None, fault);
+}
+
+#[test_case]
+fn invalid_tss() {
+    let selector = SelectorErrorCode::new_truncate(3 << 3);
+    let landing = raise_with_error_code(10, 3 << 3);
+    assert_exception(10, ErrorCode::Selector(selector), landing);
+    assert_eq!(selector.descriptor_table(), DescriptorTable::Gdt);
+}
+
+#[test_case]
+fn segment_not_present() {
+    let code = (7 << 3) | 0b100; // LDT 中下标为 7 的选择子
+    let landing = raise_with_error_code(11, code);
+    let selector = SelectorErrorCode::new_truncate(code);
+    assert_exception(11, ErrorCode::Selector(selector), landing);
+    assert_eq!(selector.descriptor_table(), DescriptorTable::Ldt);
+    assert_eq!(selector.index(), 7);
+}
+
+#[test_case]
+fn stack_segment_fault() {
+    // 以 rbp 为基址访问非规范地址会触发 #SS(0)
+    let fault: u64;
+    unsafe {
+        asm!(
+            "lea {tmp}, [rip + 2f]",
+            "mov [{resume}], {tmp}",
+            "push rbp",
+            "mov rbp, {bad}",
+            "lea {fault}, [rip + 3f]",
+            "3:",
+            "mov {tmp}, [rbp]",
+            "2:",
+            "pop rbp",
+            resume = in(reg) RESUME_AT.as_ptr(),
+            bad = in(reg) 0x8000_0000_0000_0000u64,
+            tmp = out(reg) _,
+            fault = out(reg) fault,
+        );
+    }
+    assert_exception(12, ErrorCode::Selector(SelectorErrorCode::new_truncate(0)), fault);
+}
+
+#[test_case]
+fn general_protection_fault() {
+    // 加载超出 GDT 界限的选择子
+    let (fault, _) = trigger!("mov ds, {selector:x}", selector = in(reg) 0x1230u16);
+    let selector = SelectorErrorCode::new_truncate(0x1230);
+    assert_exception(13, ErrorCode::Selector(selector), fault);
+    assert_eq!(selector.index(), 0x246);
+}
+
+#[test_case]
+fn alignment_check() {
+    // 对齐检查只在用户态生效
+    let landing = raise_with_error_code(17, 0);
+    assert_exception(17, ErrorCode::Raw(0), landing);
+}
+
+#[test_case]
+fn simd_floating_point() {
+    let (_, landing) = trigger!("int 19");
+    assert_exception(19, ErrorCode::None, landing);
+}
+
+#[test_case]
+fn virtualization() {
+    let (_, landing) = trigger!("int 20");
+    assert_exception(20, ErrorCode::None, landing);
+}
+
+#[test_case]
+fn control_protection() {
+    let landing = raise_with_error_code(21, 3);
+    assert_exception(21, ErrorCode::ControlProtection(3), landing);
+}
@@ -0,0 +1,119 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::{dealloc, Layout};
+use alloc::{boxed::Box, vec::Vec};
+use blog_os::allocator::{self, HEAP_SIZE};
+use blog_os::memory;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init(boot_info); // 初始化时会映射内核堆
+    test_main();
+
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn many_boxes() {
+    // 分配的总量远超堆大小，只有释放的内存被重用时才能成功
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+#[test_case]
+fn many_boxes_long_lived() {
+    // 一个长期存活的分配不应妨碍其余内存的重用
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+#[test_case]
+fn heap_grows_on_demand() {
+    // 一次分配比初始堆还大的内存，堆需要映射新的页面
+    let n = HEAP_SIZE * 2;
+    let mut vec = Vec::with_capacity(n);
+    for i in 0..n {
+        vec.push(i as u8);
+    }
+    assert!(allocator::heap_size() > HEAP_SIZE);
+    assert!(vec.iter().enumerate().all(|(i, &b)| b == i as u8));
+}
+
+#[test_case]
+fn large_heap_uses_huge_pages() {
+    use x86_64::structures::paging::PageTableFlags;
+    use x86_64::VirtAddr;
+
+    const HUGE_PAGE: u64 = 2 * 1024 * 1024;
+    // 堆的起始地址按 2 MiB 对齐，新映射的区域中对齐的 2 MiB 部分应当使用大页
+    let vec: Vec<u8> = Vec::with_capacity(3 * HUGE_PAGE as usize);
+    let end = VirtAddr::from_ptr(vec.as_ptr()) + vec.capacity();
+    let chunk = (end - HUGE_PAGE).align_down(HUGE_PAGE);
+    let flags = memory::inspect::flags_of(chunk).expect("heap is not mapped");
+    assert!(flags.contains(PageTableFlags::HUGE_PAGE));
+}
+
+#[test_case]
+fn allocation_while_holding_mapper() {
+    // 占满当前最大的空闲块，持有页表锁时堆无法增长，只能使用预留的内存
+    let largest = allocator::stats().largest_free_block;
+    let filler: Vec<u8> = Vec::with_capacity(largest.saturating_sub(64));
+    let inner = memory::with_mapper(|_, _| Vec::<u8>::with_capacity(16 * 1024).capacity());
+    assert!(inner >= 16 * 1024);
+    drop(filler);
+}
+
+#[test_case]
+fn try_alloc_reports_failure() {
+    let layout = Layout::from_size_align(allocator::heap_limit() * 2, 8).unwrap();
+    assert_eq!(allocator::try_alloc(layout), Err(allocator::AllocError { layout }));
+
+    let layout = Layout::from_size_align(64, 16).unwrap();
+    let ptr = allocator::try_alloc(layout).expect("small allocation failed");
+    assert!(ptr.as_ptr().align_offset(16) == 0);
+    unsafe { dealloc(ptr.as_ptr(), layout) };
+}
+
+#[test_case]
+fn try_box_allocates() {
+    let value = allocator::try_box([7u64; 16]).expect("try_box failed");
+    assert!(value.iter().all(|&v| v == 7));
+}
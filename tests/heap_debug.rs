@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use blog_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init(boot_info);
+
+    serial_print!("heap_debug::redzone_overflow...\t");
+    unsafe {
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = alloc(layout);
+        ptr.add(24).write_volatile(0); // 越界写入后红区
+        dealloc(ptr, layout); // 释放时应当检查到红区被改写
+    }
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+
+    blog_os::hlt_loop();
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use blog_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init(boot_info);
+
+    serial_print!("heap_debug_double_free::double_free...\t");
+    unsafe {
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        dealloc(ptr, layout); // 第二次释放时地址已经不在分配记录中
+    }
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+
+    blog_os::hlt_loop();
+}
+
+// 把 panic 信息的开头写入固定大小的缓冲区
+struct Buffer {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len < self.bytes.len() {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut buffer = Buffer { bytes: [0; 64], len: 0 };
+    let _ = write!(buffer, "{}", info.message());
+    if buffer.bytes[..buffer.len].starts_with(b"heap-debug: double free") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    blog_os::hlt_loop();
+}
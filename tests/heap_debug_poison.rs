@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use alloc::boxed::Box;
+use blog_os::allocator::debug::{ALLOC_POISON, FREE_POISON};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init(boot_info);
+    test_main();
+
+    blog_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn new_allocation_is_poisoned() {
+    let layout = Layout::from_size_align(128, 8).unwrap();
+    unsafe {
+        let ptr = alloc(layout);
+        assert!((0..128).all(|i| ptr.add(i).read_volatile() == ALLOC_POISON));
+        dealloc(ptr, layout);
+    }
+}
+
+#[test_case]
+fn use_after_free_reads_poison() {
+    let value = Box::new([0x11u8; 256]);
+    let ptr = Box::into_raw(value) as *const u8;
+    unsafe { drop(Box::from_raw(ptr as *mut [u8; 256])) };
+    // 分配器的元数据只写在前红区中，用户内存全部被填充为释放毒化字节
+    assert!((0..256).all(|i| unsafe { ptr.add(i).read_volatile() } == FREE_POISON));
+}
@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+use blog_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init(boot_info);
+
+    serial_print!("machine_check::machine_check...\t");
+    // 机器检查之后不能继续执行，处理函数应当 panic
+    unsafe { core::arch::asm!("int 18") };
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+
+    blog_os::hlt_loop();
+}
+
+// 把 panic 信息的开头写入固定大小的缓冲区
+struct Buffer {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len < self.bytes.len() {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut buffer = Buffer { bytes: [0; 64], len: 0 };
+    let _ = write!(buffer, "{}", info.message());
+    let expected = b"EXCEPTION: MACHINE CHECK (#MC)";
+    if buffer.bytes[..buffer.len].starts_with(expected) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    blog_os::hlt_loop();
+}
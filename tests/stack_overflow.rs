@@ -4,13 +4,18 @@
 
 use core::panic::PanicInfo;
 use blog_os::{exit_qemu, QemuExitCode, serial_print, serial_println};
+use blog_os::memory::stack;
+use bootloader::{entry_point, BootInfo};
 use lazy_static::lazy_static;
+use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::{InterruptStackFrame, InterruptDescriptorTable};
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
     serial_print!("stack_overflow::stack_overflow...\t");
 
+    blog_os::init_memory(boot_info); // IST 栈从内核虚拟地址空间分配
     blog_os::gdt::init(); // 初始化 GDT
     init_test_idt(); // 初始化测试 IDT
 
@@ -46,6 +51,8 @@ extern "x86-interrupt" fn test_double_fault_handler(
     _stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    // 溢出的是 bootloader 分配的启动栈，CR2 中是它的保护页地址
+    assert_eq!(stack::guard_page_owner(Cr2::read()), Some(stack::BOOT_STACK_NAME));
     serial_println!("[ok]\n");
     exit_qemu(QemuExitCode::Success); // 退出 QEMU
     loop {}
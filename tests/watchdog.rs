@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+use blog_os::time::tsc;
+use blog_os::{exit_qemu, serial_print, serial_println, watchdog, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init(boot_info);
+
+    serial_print!("watchdog::hard_lockup_is_reported...\t");
+    watchdog::set_threshold(Duration::from_millis(200));
+    watchdog::set_panic_on_lockup(true);
+    watchdog::enable();
+
+    // 关中断后时钟计数停止，用软件触发的 NMI 代替看门狗的硬件 NMI
+    interrupts::disable();
+    let start = tsc::read();
+    let timeout = tsc::cycles(Duration::from_secs(2)).expect("TSC not calibrated");
+    while tsc::read() - start < timeout {
+        unsafe { core::arch::asm!("int 2") };
+        for _ in 0..100_000 {
+            core::hint::spin_loop();
+        }
+    }
+    serial_println!("[failed]");
+    serial_println!("lockup was not reported");
+    exit_qemu(QemuExitCode::Failed);
+
+    blog_os::hlt_loop();
+}
+
+// 把 panic 信息的开头写入固定大小的缓冲区
+struct Buffer {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len < self.bytes.len() {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut buffer = Buffer { bytes: [0; 64], len: 0 };
+    let _ = write!(buffer, "{}", info.message());
+    let expected = b"watchdog: hard lockup";
+    if buffer.bytes[..buffer.len].starts_with(expected) && watchdog::lockups() == 1 {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: {}\n", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    blog_os::hlt_loop();
+}
@@ -0,0 +1,99 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use blog_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init_memory(boot_info); // 启用 NXE/WP 并按段设置内核映像的权限
+    init_test_idt();
+
+    write_to_code();
+}
+
+// 当前期望发生缺页的地址，0 表示不期望缺页
+static EXPECTED_FAULT: AtomicU64 = AtomicU64::new(0);
+
+fn write_to_code() -> ! {
+    serial_print!("wx_protection::write_to_code...\t");
+    let code = write_to_code as *const () as *mut u8;
+    EXPECTED_FAULT.store(code as u64, Ordering::SeqCst);
+    unsafe { code.write_volatile(0xc3) };
+    fail("write to code page succeeded");
+}
+
+// 位于 .data 中的一条 `ret` 指令
+static mut RET_IN_DATA: [u8; 1] = [0xc3];
+
+fn execute_data() -> ! {
+    serial_print!("wx_protection::execute_data...\t");
+    let data = &raw mut RET_IN_DATA as *mut u8;
+    EXPECTED_FAULT.store(data as u64, Ordering::SeqCst);
+    let function: extern "C" fn() = unsafe { core::mem::transmute(data) };
+    function();
+    fail("execution of data page succeeded");
+}
+
+fn fail(message: &str) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", message);
+    exit_qemu(QemuExitCode::Failed);
+    blog_os::hlt_loop();
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    mut stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let expected = EXPECTED_FAULT.swap(0, Ordering::SeqCst);
+    if Cr2::read().as_u64() != expected {
+        fail("page fault at unexpected address");
+    }
+    let violation = PageFaultErrorCode::PROTECTION_VIOLATION;
+    if expected == write_to_code as *const () as u64 {
+        if !error_code.contains(violation | PageFaultErrorCode::CAUSED_BY_WRITE) {
+            fail("write fault has unexpected error code");
+        }
+        serial_println!("[ok]");
+        // 跳到下一个测试继续执行，栈指针按函数入口的要求对齐
+        unsafe {
+            stack_frame.as_mut().update(|frame| {
+                frame.instruction_pointer = VirtAddr::from_ptr(execute_data as *const ());
+                frame.stack_pointer = VirtAddr::new((frame.stack_pointer.as_u64() & !0xf) - 8);
+            });
+        }
+    } else {
+        if !error_code.contains(violation | PageFaultErrorCode::INSTRUCTION_FETCH) {
+            fail("instruction fetch fault has unexpected error code");
+        }
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+        blog_os::hlt_loop();
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}